@@ -0,0 +1,42 @@
+/* Appended to link.x by build.rs with the `boot-header` feature */
+
+/* pull in the object defining the boot header */
+EXTERN(__boot_header);
+
+SECTIONS
+{
+    .nor_cfg_option ORIGIN(REGION_BOOT_HEADER) + 0x400 : {
+        KEEP(*(.nor_cfg_option))
+    } > REGION_BOOT_HEADER
+
+    .boot_header ORIGIN(REGION_BOOT_HEADER) + 0x1000 : {
+        __boot_header_start__ = .;
+        KEEP(*(.boot_header))
+        __boot_header_end__ = .;
+    } > REGION_BOOT_HEADER
+}
+
+__app_load_addr__ = ADDR(.start);
+__app_offset__ = __app_load_addr__ - __boot_header_start__;
+
+/* end of everything loaded from REGION_TEXT and REGION_RODATA */
+__fw_end__ = MAX(MAX(MAX(ADDR(.text) + SIZEOF(.text), ADDR(.rodata) + SIZEOF(.rodata)),
+                     MAX(LOADADDR(.vectors) + SIZEOF(.vectors), LOADADDR(.fast) + SIZEOF(.fast))),
+                 MAX(MAX(LOADADDR(.data) + SIZEOF(.data), LOADADDR(.fastdata) + SIZEOF(.fastdata)),
+                     MAX(LOADADDR(.noncacheable.data) + SIZEOF(.noncacheable.data),
+                         LOADADDR(.ahb_sram.data) + SIZEOF(.ahb_sram.data))));
+/* sdram.x is appended before this file */
+__fw_end__ = DEFINED(__sdram_data_load_addr__)
+    ? MAX(__fw_end__, __sdram_data_load_addr__ + (__sdram_data_end__ - __sdram_data_start__))
+    : __fw_end__;
+__fw_size__ = __fw_end__ - __app_load_addr__;
+
+ASSERT(SIZEOF(.nor_cfg_option) == 16, "
+ERROR(riscv-rt): the NOR config option is missing. Emit it with `hpm_riscv_rt::nor_cfg_option!`.");
+
+ASSERT(SIZEOF(.boot_header) == 16 + 128, "
+ERROR(riscv-rt): .boot_header must hold the boot header and exactly one firmware info table.");
+
+ASSERT(__boot_header_end__ <= __app_load_addr__, "
+ERROR(riscv-rt): the boot header overlaps the application, REGION_TEXT must start after
+ORIGIN(REGION_BOOT_HEADER) + 0x1000 + SIZEOF(.boot_header), usually at + 0x3000.");
@@ -1,9 +1,22 @@
 fn main() {
     let out_dir = std::env::var("OUT_DIR").unwrap();
     println!("cargo:rerun-if-changed=link.x");
+    println!("cargo:rerun-if-changed=boot-header.x");
+    println!("cargo:rerun-if-changed=sdram.x");
+    println!("cargo:rerun-if-changed=pmp.x");
 
     // copy link.x to the output directory
-    std::fs::copy("link.x", format!("{}/link.x", out_dir)).unwrap();
+    let mut link_x = std::fs::read_to_string("link.x").unwrap();
+    if std::env::var_os("CARGO_FEATURE_SDRAM").is_some() {
+        link_x.push_str(&std::fs::read_to_string("sdram.x").unwrap());
+    }
+    if std::env::var_os("CARGO_FEATURE_PMP_DEFAULT").is_some() {
+        link_x.push_str(&std::fs::read_to_string("pmp.x").unwrap());
+    }
+    if std::env::var_os("CARGO_FEATURE_BOOT_HEADER").is_some() {
+        link_x.push_str(&std::fs::read_to_string("boot-header.x").unwrap());
+    }
+    std::fs::write(format!("{}/link.x", out_dir), link_x).unwrap();
 
     // add the linker script to the build
     println!("cargo:rustc-link-search={}", out_dir);
@@ -0,0 +1,16 @@
+//! Host unit tests of the target independent parts of `hpm-riscv-rt`.
+//!
+//! The runtime itself only builds for RISC-V, the modules here are included from its sources and
+//! their tests run on the host. `std` is built too, `.cargo/config.toml` builds `core` from source:
+//!
+//! ```text
+//! cargo +nightly test -p hpm-riscv-rt-host-tests --target x86_64-unknown-linux-gnu -Zbuild-std=std,test
+//! ```
+
+#![cfg_attr(not(test), no_std)]
+
+#[path = "../../src/backtrace.rs"]
+pub mod backtrace;
+
+#[path = "../../src/trap/misaligned/decode.rs"]
+pub mod decode;
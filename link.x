@@ -8,22 +8,24 @@ PROVIDE(_stext = ORIGIN(REGION_TEXT));
 PROVIDE(_stack_start = ORIGIN(REGION_STACK) + LENGTH(REGION_STACK));
 PROVIDE(_max_hart_id = 0);
 PROVIDE(_hart_stack_size = 2K);
+PROVIDE(_stack_guard_size = 512);
 PROVIDE(_heap_size = 0);
 
-PROVIDE(InstructionMisaligned = ExceptionHandler);
-PROVIDE(InstructionFault = ExceptionHandler);
-PROVIDE(IllegalInstruction = ExceptionHandler);
-PROVIDE(Breakpoint = ExceptionHandler);
-PROVIDE(LoadMisaligned = ExceptionHandler);
-PROVIDE(LoadFault = ExceptionHandler);
-PROVIDE(StoreMisaligned = ExceptionHandler);
-PROVIDE(StoreFault = ExceptionHandler);;
-PROVIDE(UserEnvCall = ExceptionHandler);
-PROVIDE(SupervisorEnvCall = ExceptionHandler);
-PROVIDE(MachineEnvCall = ExceptionHandler);
-PROVIDE(InstructionPageFault = ExceptionHandler);
-PROVIDE(LoadPageFault = ExceptionHandler);
-PROVIDE(StorePageFault = ExceptionHandler);
+PROVIDE(InstructionMisaligned = DefaultExceptionSlotHandler);
+PROVIDE(InstructionFault = DefaultExceptionSlotHandler);
+PROVIDE(IllegalInstruction = DefaultExceptionSlotHandler);
+PROVIDE(Breakpoint = DefaultBreakpointHandler);
+PROVIDE(LoadMisaligned = DefaultLoadMisalignedHandler);
+PROVIDE(LoadFault = DefaultExceptionSlotHandler);
+PROVIDE(StoreMisaligned = DefaultStoreMisalignedHandler);
+PROVIDE(StoreFault = DefaultExceptionSlotHandler);
+PROVIDE(UserEnvCall = DefaultEnvCallHandler);
+PROVIDE(SupervisorEnvCall = DefaultEnvCallHandler);
+PROVIDE(MachineEnvCall = DefaultEnvCallHandler);
+PROVIDE(InstructionPageFault = DefaultExceptionSlotHandler);
+PROVIDE(LoadPageFault = DefaultExceptionSlotHandler);
+PROVIDE(StorePageFault = DefaultExceptionSlotHandler);
+PROVIDE(StackOverflow = DefaultStackOverflowHandler);
 
 PROVIDE(SupervisorSoft = DefaultCoreInterruptHandler);
 PROVIDE(MachineSoft = DefaultCoreInterruptHandler);
@@ -35,6 +37,9 @@ PROVIDE(MachineExternal = DefaultCoreInterruptHandler);
 PROVIDE(DefaultCoreInterruptHandler = DefaultInterruptHandler);
 PROVIDE(DefaultHandler = DefaultInterruptHandler);
 PROVIDE(ExceptionHandler = DefaultExceptionHandler);
+PROVIDE(SupervisorExceptionHandler = DefaultSupervisorExceptionHandler);
+PROVIDE(AllocErrorHandler = DefaultAllocErrorHandler);
+PROVIDE(CrashReset = DefaultCrashReset);
 
 
 SECTIONS
@@ -61,6 +66,7 @@ SECTIONS
 
     .text : ALIGN(8) {
         . = ALIGN(8);
+        __text_start__ = .;
         *(.text)
         *(.text*)
 
@@ -72,13 +78,65 @@ SECTIONS
 
         /* RT-Thread related sections - end */
         . = ALIGN(8);
+        __text_end__ = .;
     } > REGION_TEXT
 
     .rodata : ALIGN(8) {
         . = ALIGN(8);
+        __rodata_start__ = .;
         *(.rodata .rodata.*)
         *(.srodata .srodata.*)
+
+        /* PLIC configuration from `#[interrupt(priority = N, enable)]` */
+        . = ALIGN(4);
+        __interrupt_config_start__ = .;
+        KEEP(*(.interrupt_config))
+        __interrupt_config_end__ = .;
+
+        /* `#[interrupt(supervisor)]` handlers, put into the S-mode vector table by `smode::enter` */
+        . = ALIGN(4);
+        __supervisor_interrupts_start__ = .;
+        KEEP(*(.supervisor_interrupts))
+        __supervisor_interrupts_end__ = .;
+
+        /* `#[syscall(N)]` handlers, dispatched by `DefaultEnvCallHandler` */
+        . = ALIGN(4);
+        __syscalls_start__ = .;
+        KEEP(*(.syscalls))
+        __syscalls_end__ = .;
         . = ALIGN(8);
+        __rodata_end__ = .;
+    } > REGION_RODATA
+
+    /* Sections initialized by `_start_rust` after `__pre_init`, as records of (load, start, end)
+       and (start, end) words. Add entries from a user `memory.x` with
+       SECTIONS { .copy_table.my_ram : { LONG(load) LONG(start) LONG(end) } > REGION_RODATA }
+       INSERT AFTER .copy_table;
+       and the same for `.zero_table`. */
+    .copy_table : ALIGN(4) {
+        __copy_table_start__ = .;
+        LONG(__data_load_addr__) LONG(__data_start__) LONG(__data_end__)
+        LONG(__fast_data_load_addr__) LONG(__fast_data_start__) LONG(__fast_data_end__)
+        LONG(__noncacheable_data_load_addr__) LONG(__noncacheable_data_start__) LONG(__noncacheable_data_end__)
+        LONG(__ahb_sram_data_load_addr__) LONG(__ahb_sram_data_start__) LONG(__ahb_sram_data_end__)
+        LONG(__user_data_load_addr__) LONG(__user_data_start__) LONG(__user_data_end__)
+    } > REGION_RODATA
+
+    .copy_table.end : {
+        __copy_table_end__ = .;
+    } > REGION_RODATA
+
+    .zero_table : ALIGN(4) {
+        __zero_table_start__ = .;
+        LONG(__bss_start__) LONG(__bss_end__)
+        LONG(__fast_bss_start__) LONG(__fast_bss_end__)
+        LONG(__noncacheable_bss_start__) LONG(__noncacheable_bss_end__)
+        LONG(__ahb_sram_bss_start__) LONG(__ahb_sram_bss_end__)
+        LONG(__user_bss_start__) LONG(__user_bss_end__)
+    } > REGION_RODATA
+
+    .zero_table.end : {
+        __zero_table_end__ = .;
     } > REGION_RODATA
 
     .data : ALIGN(8) {
@@ -137,22 +195,55 @@ SECTIONS
     . = ALIGN(8);
     __bss_end__ = .;
 
-    /* Non-cacheable data and bss */
-    .noncacheable.data : ALIGN(8) {
+    /* Never initialized, keeps its content across warm resets as long as the layout stays the same */
+    .noinit (NOLOAD) : ALIGN(4) {
+        __noinit_start__ = .;
+        KEEP(*(.noinit .noinit.*))
+        . = ALIGN(4);
+        __noinit_end__ = .;
+    } > REGION_DATA
+
+    /* `#[place(user)]`, the memory U-mode code started by `umode::enter` may write */
+    .user.data : ALIGN(8) {
+        __user_data_start__ = .;
+        KEEP(*(.user.data .user.data.*))
+        . = ALIGN(4);
+        __user_data_end__ = .;
+    } > REGION_DATA AT > REGION_RODATA
+
+    __user_data_load_addr__ = LOADADDR(.user.data);
+
+    .user.bss (NOLOAD) : ALIGN(4) {
+        __user_bss_start__ = .;
+        KEEP(*(.user.bss .user.bss.*))
+        . = ALIGN(4);
+        __user_bss_end__ = .;
+        /* not initialized */
+        KEEP(*(.user .user.noinit .user.noinit.*))
+        . = ALIGN(8);
+        __user_end__ = .;
+    } > REGION_DATA
+
+    /* Non-cacheable data and bss, aligned so that a single PMA NAPOT entry covers both.
+       `.noncacheable.bss` starts on the cache line after `.noncacheable.data` */
+    __noncacheable_napot_size__ = MAX(4K, 1 << LOG2CEIL(ALIGN(SIZEOF(.noncacheable.data), 64) + SIZEOF(.noncacheable.bss)));
+
+    /* every input section starts on its own cache line, see `#[noncacheable]` */
+    .noncacheable.data : ALIGN(__noncacheable_napot_size__) SUBALIGN(64) {
         . = ALIGN(8);
         __noncacheable_data_start__ = .;
-        KEEP(*(.noncacheable.data))
+        KEEP(*(.noncacheable.data .noncacheable.data.*))
         . = ALIGN(8);
         __noncacheable_data_end__ = .;
     } > REGION_NONCACHEABLE_RAM AT > REGION_RODATA
 
     __noncacheable_data_load_addr__ = LOADADDR(.noncacheable.data);
 
-    .noncacheable.bss (NOLOAD) : {
+    .noncacheable.bss (NOLOAD) : SUBALIGN(64) {
         . = ALIGN(8);
         KEEP(*(.noncacheable))
         __noncacheable_bss_start__ = .;
-        KEEP(*(.noncacheable.bss))
+        KEEP(*(.noncacheable.bss .noncacheable.bss.*))
         __noncacheable_bss_end__ = .;
         . = ALIGN(8);
     } > REGION_NONCACHEABLE_RAM
@@ -163,8 +254,23 @@ SECTIONS
     } > SHARE_RAM
     */
 
-    .ahb_sram (NOLOAD) : {
-        KEEP(*(.ahb_sram))
+    /* `#[place(ahb_sram)]` */
+    .ahb_sram.data : ALIGN(4) {
+        __ahb_sram_data_start__ = .;
+        KEEP(*(.ahb_sram.data .ahb_sram.data.*))
+        . = ALIGN(4);
+        __ahb_sram_data_end__ = .;
+    } > AHB_SRAM AT > REGION_RODATA
+
+    __ahb_sram_data_load_addr__ = LOADADDR(.ahb_sram.data);
+
+    .ahb_sram.bss (NOLOAD) : ALIGN(4) {
+        __ahb_sram_bss_start__ = .;
+        KEEP(*(.ahb_sram.bss .ahb_sram.bss.*))
+        . = ALIGN(4);
+        __ahb_sram_bss_end__ = .;
+        /* not initialized */
+        KEEP(*(.ahb_sram .ahb_sram.noinit .ahb_sram.noinit.*))
     } > AHB_SRAM
 
     .heap (NOLOAD) :
@@ -210,10 +316,30 @@ ASSERT(ORIGIN(REGION_STACK) % 4 == 0, "
 ERROR(riscv-rt): the start of the REGION_STACK must be 4-byte aligned");
 
 
+ASSERT(__noncacheable_data_start__ % __noncacheable_napot_size__ == 0 &&
+       __noncacheable_bss_end__ <= __noncacheable_data_start__ + __noncacheable_napot_size__ &&
+       __noncacheable_data_start__ + __noncacheable_napot_size__ <=
+       ORIGIN(REGION_NONCACHEABLE_RAM) + LENGTH(REGION_NONCACHEABLE_RAM), "
+ERROR(riscv-rt): the non-cacheable sections can not be covered by one PMA NAPOT entry.
+`.noncacheable.data` must be aligned to the size of `.noncacheable.*` rounded up to a power
+of 2 (at least 4K), and that aligned window must fit in REGION_NONCACHEABLE_RAM.");
+
+/* The core local ILM/DLM windows are below 0x01000000, each hart sees its own memories there */
+ASSERT(_max_hart_id == 0 || __noncacheable_data_start__ >= 0x01000000, "
+ERROR(riscv-rt): with `_max_hart_id` > 0, REGION_NONCACHEABLE_RAM holds the state shared by
+the harts and must not be core local ILM/DLM. Use e.g. AXI_SRAM.");
+
+ASSERT(!DEFINED(__hpm_riscv_rt_lock_owner) || __hpm_riscv_rt_lock_owner >= 0x01000000, "
+ERROR(riscv-rt): `critical-section-multi-hart` needs REGION_NONCACHEABLE_RAM shared by the harts,
+it must not be core local ILM/DLM. Use e.g. AXI_SRAM.");
+
 ASSERT(SIZEOF(.stack) > (_max_hart_id + 1) * _hart_stack_size, "
 ERROR(riscv-rt): .stack section is too small for allocating stacks for all the harts.
 Consider changing `_max_hart_id` or `_hart_stack_size`.");
 
+ASSERT(_stack_guard_size % 16 == 0 && _stack_guard_size < _hart_stack_size, "
+ERROR(riscv-rt): `_stack_guard_size` must be a multiple of 16 and smaller than `_hart_stack_size`.");
+
 /* # Other checks */
 ASSERT(SIZEOF(.got) == 0, "
 ERROR(riscv-rt): .got section detected in the input files. Dynamic relocations are not
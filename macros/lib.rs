@@ -1,7 +1,7 @@
 use proc_macro2::Span;
 use proc_macro_error::proc_macro_error;
 use syn::{
-    parse, parse_macro_input, spanned::Spanned, token, Abi, Expr, Ident, Item, ItemFn, LitStr,
+    parse, parse_macro_input, spanned::Spanned, token, Abi, Expr, Ident, Item, ItemFn, Lit, LitStr,
     ReturnType, Type, Visibility,
 };
 
@@ -67,6 +67,67 @@ pub fn entry(args: TokenStream, input: TokenStream) -> TokenStream {
     .into()
 }
 
+/// Marks a function as the entry point of the secondary harts.
+///
+/// It is called with the hart id once the boot hart has initialized memory.
+#[proc_macro_attribute]
+pub fn hart_entry(args: TokenStream, input: TokenStream) -> TokenStream {
+    use syn::FnArg;
+
+    let f = parse_macro_input!(input as ItemFn);
+
+    // check the function signature
+    let valid_signature = f.sig.constness.is_none()
+        && f.sig.asyncness.is_none()
+        && f.vis == Visibility::Inherited
+        && f.sig.abi.is_none()
+        && f.sig.inputs.len() == 1
+        // `_start` passes `mhartid` in `a0`
+        && match f.sig.inputs.first() {
+            Some(FnArg::Typed(arg)) => {
+                matches!(*arg.ty, Type::Path(ref p) if p.qself.is_none() && p.path.is_ident("usize"))
+            }
+            _ => false,
+        }
+        && f.sig.generics.params.is_empty()
+        && f.sig.generics.where_clause.is_none()
+        && f.sig.variadic.is_none()
+        && match f.sig.output {
+            ReturnType::Default => false,
+            ReturnType::Type(_, ref ty) => matches!(**ty, Type::Never(_)),
+        };
+
+    if !valid_signature {
+        return parse::Error::new(
+            f.span(),
+            "`#[hart_entry]` function must have signature `[unsafe] fn(hartid: usize) -> !`",
+        )
+        .to_compile_error()
+        .into();
+    }
+
+    if !args.is_empty() {
+        return parse::Error::new(Span::call_site(), "This attribute accepts no arguments")
+            .to_compile_error()
+            .into();
+    }
+
+    let attrs = f.attrs;
+    let unsafety = f.sig.unsafety;
+    let args = f.sig.inputs;
+    let stmts = f.block.stmts;
+
+    quote!(
+        #[allow(non_snake_case)]
+        #[export_name = "hart_main"]
+        #(#attrs)*
+        pub #unsafety extern "C" fn __hpm_riscv_v_rt__hart_main(#args) -> ! {
+            #(#stmts)*
+        }
+    )
+    .into()
+}
+
 /// This attribute allows placing functions into ram.
 #[proc_macro_attribute]
 #[proc_macro_error]
@@ -117,6 +178,235 @@ pub fn fast(_args: TokenStream, input: TokenStream) -> TokenStream {
     }
 }
 
+/// Whether a static initializer is all zero bytes, so the static can go to a `.bss` section.
+fn is_zero_init(expr: &Expr) -> bool {
+    match expr {
+        Expr::Lit(l) => match &l.lit {
+            Lit::Int(i) => i.base10_digits() == "0",
+            Lit::Float(f) => f.base10_parse::<f64>().is_ok_and(|f| f == 0.0),
+            Lit::Bool(b) => !b.value,
+            _ => false,
+        },
+        Expr::Repeat(r) => is_zero_init(&r.expr),
+        Expr::Array(a) => a.elems.iter().all(is_zero_init),
+        Expr::Tuple(t) => t.elems.iter().all(is_zero_init),
+        Expr::Paren(p) => is_zero_init(&p.expr),
+        Expr::Group(g) => is_zero_init(&g.expr),
+        Expr::Call(c) => {
+            let s = format!("{}", c.func.to_token_stream());
+            let ty = s.rsplit(" :: ").nth(1).unwrap_or_default();
+            if s.ends_with("MaybeUninit :: uninit")
+                || s.ends_with("MaybeUninit :: uninit_array")
+                || s.ends_with("MaybeUninit :: zeroed")
+            {
+                c.args.is_empty()
+            } else {
+                // `AtomicU32::new(0)`, `AtomicBool::new(false)`
+                ty.starts_with("Atomic")
+                    && s.ends_with(":: new")
+                    && c.args.len() == 1
+                    && is_zero_init(&c.args[0])
+            }
+        }
+        _ => false,
+    }
+}
+
+/// This attribute places a static into non-cacheable RAM, for DMA buffers and memory shared by harts.
+///
+/// Zero initialized statics go to `.noncacheable.bss`, others to `.noncacheable.data`.
+/// Each static gets its own section, which link.x aligns to a cache line, so it never shares a
+/// line with other data.
+///
+/// Usage:
+/// ```ignore
+/// #[noncacheable]
+/// static mut RX_BUF: [u8; 512] = [0; 512];
+/// ```
+#[proc_macro_attribute]
+pub fn noncacheable(args: TokenStream, input: TokenStream) -> TokenStream {
+    let item = parse_macro_input!(input as Item);
+
+    if !args.is_empty() {
+        return parse::Error::new(Span::call_site(), "`#[noncacheable]` accepts no arguments")
+            .to_compile_error()
+            .into();
+    }
+
+    let item = match item {
+        Item::Static(item) => item,
+        _ => {
+            let msg = "`#[noncacheable]` expected static";
+            return syn::Error::new(item.span(), msg).to_compile_error().into();
+        }
+    };
+
+    let flavour = if is_zero_init(&item.expr) {
+        "bss"
+    } else {
+        "data"
+    };
+    let section = format!(".noncacheable.{}.{}", flavour, item.ident);
+    let ty = &item.ty;
+
+    quote!(
+        #[link_section = #section]
+        #item
+
+        // link.x forces the alignment of the section to exactly a cache line
+        const _: () = ::core::assert!(
+            ::core::mem::align_of::<#ty>() <= ::hpm_riscv_rt::cache::CACHE_LINE_SIZE,
+            "`#[noncacheable]` statics can not be aligned to more than a cache line"
+        );
+    )
+    .into()
+}
+
+/// This attribute places a static into a memory region, for regions without an alias in link.x.
+///
+/// `#[place(region)]` puts the static into `.region.data` or `.region.bss`, chosen from the initializer,
+/// `#[place(region, noinit)]` into `.region.noinit`, which is never initialized.
+/// `ahb_sram` and `sdram` are initialized by `_start_rust` after `__pre_init`, so SDRAM can be set up
+/// there, `sdram` requires the `sdram` feature. `user` is the RAM writable by U-mode code. Other
+/// regions need their own output sections in `memory.x`. Like `#[noinit]`, `noinit` statics must be
+/// `MaybeUninit`.
+///
+/// Usage:
+/// ```ignore
+/// #[place(sdram)]
+/// static mut FRAME_BUFFER: [u16; 480 * 272] = [0; 480 * 272];
+///
+/// #[place(ahb_sram, noinit)]
+/// static mut LOG: MaybeUninit<[u8; 1024]> = MaybeUninit::uninit();
+/// ```
+#[proc_macro_attribute]
+pub fn place(args: TokenStream, input: TokenStream) -> TokenStream {
+    use syn::{AttributeArgs, Meta, NestedMeta};
+
+    let item = parse_macro_input!(input as Item);
+    let args: AttributeArgs = parse_macro_input!(args as AttributeArgs);
+
+    let usage = "Accept form: #[place(region)], #[place(region, noinit)]";
+    let region = match args.first() {
+        Some(NestedMeta::Meta(Meta::Path(p))) if p.get_ident().is_some() => {
+            p.get_ident().unwrap().clone()
+        }
+        _ => {
+            return parse::Error::new(Span::call_site(), usage)
+                .to_compile_error()
+                .into();
+        }
+    };
+    let noinit = match args.get(1) {
+        None => false,
+        Some(NestedMeta::Meta(Meta::Path(p))) if p.is_ident("noinit") && args.len() == 2 => true,
+        Some(arg) => {
+            return parse::Error::new(arg.span(), usage)
+                .to_compile_error()
+                .into();
+        }
+    };
+
+    if region == "sdram" && !cfg!(feature = "sdram") {
+        return parse::Error::new(
+            region.span(),
+            "`sdram` requires the `sdram` feature of hpm-riscv-rt",
+        )
+        .to_compile_error()
+        .into();
+    }
+
+    let item = match item {
+        Item::Static(item) => item,
+        _ => {
+            let msg = "`#[place]` expected static";
+            return syn::Error::new(item.span(), msg).to_compile_error().into();
+        }
+    };
+
+    if noinit && !is_maybe_uninit(&item.ty) {
+        return parse::Error::new(
+            item.ty.span(),
+            "`#[place(region, noinit)]` static must be of type `MaybeUninit<T>`",
+        )
+        .to_compile_error()
+        .into();
+    }
+
+    let flavour = if noinit {
+        "noinit"
+    } else if is_zero_init(&item.expr) {
+        "bss"
+    } else {
+        "data"
+    };
+    let section = format!(".{}.{}.{}", region, flavour, item.ident);
+
+    quote!(
+        #[link_section = #section]
+        #item
+    )
+    .into()
+}
+
+/// This attribute places a static into `.noinit`, which the runtime never initializes, so its
+/// content survives warm resets.
+///
+/// The static must be a `MaybeUninit`, the content after a cold reset is random.
+///
+/// Usage:
+/// ```ignore
+/// #[noinit]
+/// static mut BOOT_COUNT: MaybeUninit<u32> = MaybeUninit::uninit();
+/// ```
+#[proc_macro_attribute]
+pub fn noinit(args: TokenStream, input: TokenStream) -> TokenStream {
+    let item = parse_macro_input!(input as Item);
+
+    if !args.is_empty() {
+        return parse::Error::new(Span::call_site(), "`#[noinit]` accepts no arguments")
+            .to_compile_error()
+            .into();
+    }
+
+    let item = match item {
+        Item::Static(item) => item,
+        _ => {
+            let msg = "`#[noinit]` expected static";
+            return syn::Error::new(item.span(), msg).to_compile_error().into();
+        }
+    };
+
+    if !is_maybe_uninit(&item.ty) {
+        return parse::Error::new(
+            item.ty.span(),
+            "`#[noinit]` static must be of type `MaybeUninit<T>`",
+        )
+        .to_compile_error()
+        .into();
+    }
+
+    let section = format!(".noinit.{}", item.ident);
+
+    quote!(
+        #[link_section = #section]
+        #item
+    )
+    .into()
+}
+
+/// Whether `ty` is a `MaybeUninit<T>`, the only sound type for never initialized memory
+fn is_maybe_uninit(ty: &Type) -> bool {
+    match ty {
+        Type::Path(p) => p
+            .path
+            .segments
+            .last()
+            .is_some_and(|s| s.ident == "MaybeUninit"),
+        _ => false,
+    }
+}
+
 const CORE_INTERRUPTS: [&str; 6] = [
     "SupervisorSoft",
     "MachineSoft",
@@ -139,6 +429,16 @@ const CORE_INTERRUPTS: [&str; 6] = [
 ///
 /// #[interrupt(MachineTimer)]
 /// fn SysTick() { ... }
+///
+/// // set PLIC priority and enable the interrupt during startup,
+/// // requires the PAC's `Interrupt` enum in scope
+/// #[interrupt(priority = 2, enable)]
+/// fn GPIO0_A() { ... }
+///
+/// // S-mode handler in the vector table of `smode::enter`, enabled for PLIC target 1,
+/// // requires the PAC's `Interrupt` enum in scope
+/// #[interrupt(supervisor, priority = 1, enable)]
+/// fn GPIO0_B() { ... }
 /// ```
 #[proc_macro_attribute]
 pub fn interrupt(args: TokenStream, input: TokenStream) -> TokenStream {
@@ -149,19 +449,27 @@ pub fn interrupt(args: TokenStream, input: TokenStream) -> TokenStream {
     let mut link_name = f.sig.ident.to_string();
     let mut is_core_irq = false;
 
-    if !args.is_empty() {
-        let args: AttributeArgs = parse_macro_input!(args as AttributeArgs);
-        if args.len() > 1 {
-            return parse::Error::new(
-                Span::call_site(),
-                "Accept form: #[interrupt], #[interrupt(InterruptName)], #[interrupt(InterruptName)]",
-            )
-            .to_compile_error()
-            .into();
-        }
-
-        if let NestedMeta::Meta(Meta::Path(ref p)) = args[0] {
-            if let Some(ident) = p.get_ident() {
+    let mut priority: Option<u32> = None;
+    let mut enable = false;
+    let mut supervisor = false;
+
+    let args: AttributeArgs = parse_macro_input!(args as AttributeArgs);
+    for (i, arg) in args.iter().enumerate() {
+        match arg {
+            NestedMeta::Meta(Meta::Path(p)) if p.is_ident("enable") => enable = true,
+            NestedMeta::Meta(Meta::Path(p)) if p.is_ident("supervisor") => {
+                if !cfg!(feature = "smode") {
+                    return parse::Error::new(
+                        p.span(),
+                        "`supervisor` requires the `smode` feature of hpm-riscv-rt",
+                    )
+                    .to_compile_error()
+                    .into();
+                }
+                supervisor = true
+            }
+            NestedMeta::Meta(Meta::Path(p)) if i == 0 && p.get_ident().is_some() => {
+                let ident = p.get_ident().unwrap();
                 if let Some(irq_name) = CORE_INTERRUPTS.iter().find(|s| ident == *s) {
                     link_name = irq_name.to_string();
                     is_core_irq = true;
@@ -169,19 +477,41 @@ pub fn interrupt(args: TokenStream, input: TokenStream) -> TokenStream {
                     link_name = ident.to_string();
                 }
             }
-        } else {
-            return parse::Error::new(
-                Span::call_site(),
-                "Wrong type of argument, expected a core interrupt name",
-            )
-            .to_compile_error()
-            .into();
-        }
-    } else {
-        if CORE_INTERRUPTS.iter().any(|s| link_name == *s) {
-            is_core_irq = true;
+            NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("priority") => {
+                // 0 never interrupts, the PLIC ignores bits above 7
+                match &nv.lit {
+                    Lit::Int(n) if matches!(n.base10_parse::<u32>(), Ok(1..=7)) => {
+                        priority = Some(n.base10_parse().unwrap())
+                    }
+                    _ => {
+                        return parse::Error::new(nv.lit.span(), "expected a priority from 1 to 7")
+                            .to_compile_error()
+                            .into();
+                    }
+                }
+            }
+            _ => {
+                return parse::Error::new(
+                    arg.span(),
+                    "Accept form: #[interrupt], #[interrupt(InterruptName)], #[interrupt([InterruptName,] [supervisor,] [priority = N,] [enable])]",
+                )
+                .to_compile_error()
+                .into();
+            }
         }
     }
+    if args.is_empty() && CORE_INTERRUPTS.iter().any(|s| link_name == *s) {
+        is_core_irq = true;
+    }
+
+    if is_core_irq && (priority.is_some() || enable || supervisor) {
+        return parse::Error::new(
+            Span::call_site(),
+            "`supervisor`, `priority` and `enable` only apply to external(PLIC) interrupts",
+        )
+        .to_compile_error()
+        .into();
+    }
 
     // check the function arguments
     if !f.sig.inputs.is_empty() {
@@ -218,7 +548,59 @@ pub fn interrupt(args: TokenStream, input: TokenStream) -> TokenStream {
         .into();
     }
 
-    if !is_core_irq {
+    if supervisor {
+        f.sig.abi = Some(Abi {
+            extern_token: token::Extern(Span::call_site()),
+            name: Some(LitStr::new("riscv-interrupt-s", Span::call_site())),
+        });
+        f.sig.unsafety = Some(token::Unsafe(Span::call_site()))
+    } else if !is_core_irq {
+        // Save the trap CSRs and re-enable MIE, so that the PLIC can preempt this handler
+        // with a higher priority interrupt. The `mret` of a preempting handler resets
+        // `mstatus.MPP` and `MPIE`, so `mstatus` is saved too.
+        if cfg!(feature = "nested-interrupts") {
+            let unsafety = f.sig.unsafety;
+            let output = &f.sig.output;
+            let block = &f.block;
+            f.block = syn::parse_quote!({
+                #[inline(always)]
+                #unsafety fn __handler() #output #block
+
+                let mepc: usize;
+                let mcause: usize;
+                let mxstatus: usize;
+                let mstatus: usize;
+                ::core::arch::asm!(
+                    "csrr {0}, mepc",
+                    "csrr {1}, mcause",
+                    "csrr {2}, 0x7c4", // mxstatus
+                    "csrr {3}, mstatus",
+                    "csrsi mstatus, 8", // MIE
+                    out(reg) mepc,
+                    out(reg) mcause,
+                    out(reg) mxstatus,
+                    out(reg) mstatus,
+                );
+
+                #[allow(unreachable_code)]
+                {
+                    __handler();
+
+                    ::core::arch::asm!(
+                        "csrci mstatus, 8",
+                        "csrw mstatus, {3}",
+                        "csrw 0x7c4, {2}",
+                        "csrw mcause, {1}",
+                        "csrw mepc, {0}",
+                        in(reg) mepc,
+                        in(reg) mcause,
+                        in(reg) mxstatus,
+                        in(reg) mstatus,
+                    );
+                }
+            });
+        }
+
         f.sig.abi = Some(Abi {
             extern_token: token::Extern(Span::call_site()),
             name: Some(LitStr::new("riscv-interrupt-m", Span::call_site())),
@@ -233,15 +615,317 @@ pub fn interrupt(args: TokenStream, input: TokenStream) -> TokenStream {
 
     f.sig.ident = Ident::new(&link_name, Span::call_site());
 
+    // registered in `.interrupt_config`, applied by `_setup_interrupts`.
+    // The IRQ number comes from the PAC's `Interrupt` enum, which must be in scope.
+    let config = if priority.is_some() || enable {
+        let ident = &f.sig.ident;
+        let config_ident = Ident::new(
+            &format!("__HPM_RISCV_RT_INTERRUPT_CONFIG_{}", link_name),
+            Span::call_site(),
+        );
+        // priority 0 never interrupts
+        let priority = priority.unwrap_or(1);
+        quote!(
+            #[used]
+            #[doc(hidden)]
+            #[link_section = ".interrupt_config"]
+            static #config_ident: ::hpm_riscv_rt::InterruptConfig = ::hpm_riscv_rt::InterruptConfig {
+                irq: Interrupt::#ident as u16,
+                priority: #priority,
+                enable: #enable,
+                supervisor: #supervisor,
+            };
+        )
+    } else {
+        quote!()
+    };
+
+    // S-mode handlers are not exported, the PAC's M-mode vector table refers to the same names.
+    // They are registered in `.supervisor_interrupts`, put into the S-mode vector table by
+    // `smode::enter`.
+    if supervisor {
+        let ident = &f.sig.ident;
+        let entry_ident = Ident::new(
+            &format!("__HPM_RISCV_RT_SUPERVISOR_INTERRUPT_{}", link_name),
+            Span::call_site(),
+        );
+        return quote!(
+            #[allow(non_snake_case)]
+            #[link_section = ".isr_s_vector"]
+            #f
+
+            #[used]
+            #[doc(hidden)]
+            #[link_section = ".supervisor_interrupts"]
+            static #entry_ident: ::hpm_riscv_rt::smode::SupervisorInterrupt =
+                ::hpm_riscv_rt::smode::SupervisorInterrupt {
+                    irq: Interrupt::#ident as u16,
+                    handler: #ident as *const (),
+                };
+
+            #config
+        )
+        .into();
+    }
+
+    if is_core_irq {
+        return quote!(
+            #[allow(non_snake_case)]
+            #[link_section = ".isr_vector"]
+            #[no_mangle]
+            #f
+
+            #config
+        )
+        .into();
+    }
+
+    // The PLIC vectors to the exported symbol, a stub that moves to the machine stack when the
+    // interrupt was taken in U- or S-mode, see `__hpm_riscv_rt_external_enter` in the runtime.
+    let handler_name = format!("__hpm_riscv_rt_isr_{}", link_name);
+    let stub = format!(
+        r#"
+    .section .isr_vector, "ax"
+    .p2align 2
+    .global {link_name}
+{link_name}:
+    // mscratch is the machine stack pointer while in U- or S-mode, 0 in M-mode
+    csrrw sp, mscratch, sp
+    bnez sp, 1f
+    csrrw sp, mscratch, sp
+    j {handler_name}
+1:
+    addi sp, sp, -32
+    sw ra, 0(sp)
+    jal ra, __hpm_riscv_rt_external_enter
+    j {handler_name}
+"#
+    );
+
     quote!(
         #[allow(non_snake_case)]
         #[link_section = ".isr_vector"]
+        #[export_name = #handler_name]
+        #f
+
+        ::core::arch::global_asm!(#stub);
+
+        #config
+    )
+    .into()
+}
+
+const EXCEPTIONS: [&str; 15] = [
+    "InstructionMisaligned",
+    "InstructionFault",
+    "IllegalInstruction",
+    "Breakpoint",
+    "LoadMisaligned",
+    "LoadFault",
+    "StoreMisaligned",
+    "StoreFault",
+    "UserEnvCall",
+    "SupervisorEnvCall",
+    "MachineEnvCall",
+    "InstructionPageFault",
+    "LoadPageFault",
+    "StorePageFault",
+    "StackOverflow",
+];
+
+/// Marks a function as the handler of a specific exception.
+///
+/// The returned `ExceptionAction` tells the runtime whether to resume, skip the faulting
+/// instruction or escalate to `ExceptionHandler`.
+///
+/// Usage:
+/// ```ignore
+/// #[exception(LoadFault)]
+/// fn load_fault(trap_frame: &mut TrapFrame) -> ExceptionAction { ... }
+///
+/// #[exception]
+/// fn LoadMisaligned(trap_frame: &mut TrapFrame) -> ExceptionAction { ... }
+/// ```
+#[proc_macro_attribute]
+pub fn exception(args: TokenStream, input: TokenStream) -> TokenStream {
+    use syn::{AttributeArgs, FnArg, Meta, NestedMeta};
+
+    let mut f = parse_macro_input!(input as ItemFn);
+
+    let mut link_name = f.sig.ident.to_string();
+
+    if !args.is_empty() {
+        let args: AttributeArgs = parse_macro_input!(args as AttributeArgs);
+        if args.len() > 1 {
+            return parse::Error::new(
+                Span::call_site(),
+                "Accept form: #[exception], #[exception(ExceptionName)]",
+            )
+            .to_compile_error()
+            .into();
+        }
+
+        match args[0] {
+            NestedMeta::Meta(Meta::Path(ref p)) if p.get_ident().is_some() => {
+                link_name = p.get_ident().unwrap().to_string();
+            }
+            _ => {
+                return parse::Error::new(
+                    Span::call_site(),
+                    "Wrong type of argument, expected an exception name",
+                )
+                .to_compile_error()
+                .into();
+            }
+        }
+    }
+
+    if !EXCEPTIONS.contains(&&*link_name) {
+        return parse::Error::new(
+            Span::call_site(),
+            format!(
+                "`{}` is not an exception, expected one of: {}",
+                link_name,
+                EXCEPTIONS.join(", ")
+            ),
+        )
+        .to_compile_error()
+        .into();
+    }
+
+    let valid_signature = f.sig.constness.is_none()
+        && f.sig.asyncness.is_none()
+        && f.vis == Visibility::Inherited
+        && f.sig.abi.is_none()
+        && f.sig.generics.params.is_empty()
+        && f.sig.generics.where_clause.is_none()
+        && f.sig.variadic.is_none()
+        && f.sig.inputs.len() == 1
+        && match f.sig.inputs[0] {
+            FnArg::Typed(ref arg) => {
+                matches!(*arg.ty, Type::Reference(ref r) if r.mutability.is_some())
+            }
+            FnArg::Receiver(_) => false,
+        }
+        && match f.sig.output {
+            ReturnType::Default => false,
+            ReturnType::Type(_, ref ty) => matches!(**ty, Type::Path(_)),
+        };
+
+    if !valid_signature {
+        return parse::Error::new(
+            f.span(),
+            "`#[exception]` handlers must have signature `[unsafe] fn(&mut TrapFrame) -> ExceptionAction`",
+        )
+        .to_compile_error()
+        .into();
+    }
+
+    f.sig.abi = Some(Abi {
+        extern_token: token::Extern(Span::call_site()),
+        name: Some(LitStr::new("C", Span::call_site())),
+    });
+
+    let ident = Ident::new(&link_name, Span::call_site());
+    f.sig.ident = ident.clone();
+
+    quote!(
+        // checks the types, the symbol is only referenced by the linker
+        const _: unsafe extern "C" fn(
+            &mut ::hpm_riscv_rt::trap::TrapFrame,
+        ) -> ::hpm_riscv_rt::trap::ExceptionAction = #ident;
+
+        #[allow(non_snake_case)]
         #[no_mangle]
         #f
     )
     .into()
 }
 
+/// Marks a function as the handler of syscall `N`, called for an `ecall` with `N` in `a7`.
+///
+/// Arguments are in the saved `a0`..`a6`, the returned value is written to `a0`. Each number may
+/// only have one handler, a duplicate fails to build.
+///
+/// Usage:
+/// ```ignore
+/// #[syscall(1)]
+/// fn write(trap_frame: &mut TrapFrame) -> usize { ... }
+/// ```
+#[proc_macro_attribute]
+pub fn syscall(args: TokenStream, input: TokenStream) -> TokenStream {
+    use syn::{AttributeArgs, FnArg, NestedMeta};
+
+    let mut f = parse_macro_input!(input as ItemFn);
+
+    let args: AttributeArgs = parse_macro_input!(args as AttributeArgs);
+    let number = match args.as_slice() {
+        [NestedMeta::Lit(Lit::Int(n))] if n.base10_parse::<u32>().is_ok() => {
+            n.base10_parse::<u32>().unwrap()
+        }
+        _ => {
+            return parse::Error::new(Span::call_site(), "Accept form: #[syscall(N)]")
+                .to_compile_error()
+                .into();
+        }
+    };
+
+    let valid_signature = f.sig.constness.is_none()
+        && f.sig.asyncness.is_none()
+        && f.vis == Visibility::Inherited
+        && f.sig.abi.is_none()
+        && f.sig.generics.params.is_empty()
+        && f.sig.generics.where_clause.is_none()
+        && f.sig.variadic.is_none()
+        && f.sig.inputs.len() == 1
+        && match f.sig.inputs[0] {
+            FnArg::Typed(ref arg) => {
+                matches!(*arg.ty, Type::Reference(ref r) if r.mutability.is_some())
+            }
+            FnArg::Receiver(_) => false,
+        }
+        && match f.sig.output {
+            ReturnType::Default => false,
+            ReturnType::Type(_, ref ty) => matches!(**ty, Type::Path(_)),
+        };
+
+    if !valid_signature {
+        return parse::Error::new(
+            f.span(),
+            "`#[syscall]` handlers must have signature `[unsafe] fn(&mut TrapFrame) -> usize`",
+        )
+        .to_compile_error()
+        .into();
+    }
+
+    f.sig.abi = Some(Abi {
+        extern_token: token::Extern(Span::call_site()),
+        name: Some(LitStr::new("C", Span::call_site())),
+    });
+
+    let ident = &f.sig.ident;
+    // exported, so a second handler for the same number is a duplicate symbol
+    let entry_ident = Ident::new(
+        &format!("__HPM_RISCV_RT_SYSCALL_{}", number),
+        Span::call_site(),
+    );
+    let number = number as usize;
+
+    quote!(
+        #f
+
+        #[used]
+        #[doc(hidden)]
+        #[no_mangle]
+        #[link_section = ".syscalls"]
+        static #entry_ident: ::hpm_riscv_rt::trap::Syscall = ::hpm_riscv_rt::trap::Syscall {
+            number: #number,
+            handler: #ident,
+        };
+    )
+    .into()
+}
+
 #[proc_macro_attribute]
 pub fn pre_init(args: TokenStream, input: TokenStream) -> TokenStream {
     let f = parse_macro_input!(input as ItemFn);
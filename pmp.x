@@ -0,0 +1,27 @@
+/* Appended to link.x by build.rs with the `pmp-default` feature */
+
+/* No-access NAPOT region below `__stack_end__`, a power of two of at least 16 bytes */
+PROVIDE(_pmp_stack_guard_size = 1K);
+
+SECTIONS
+{
+    .stack_guard (NOLOAD) : ALIGN(_pmp_stack_guard_size) {
+        __pmp_stack_guard_start__ = .;
+        . += _pmp_stack_guard_size;
+        __pmp_stack_guard_end__ = .;
+    } > REGION_STACK
+}
+INSERT BEFORE .stack;
+
+ASSERT(_pmp_stack_guard_size >= 16 && (_pmp_stack_guard_size & (_pmp_stack_guard_size - 1)) == 0, "
+ERROR(riscv-rt): `_pmp_stack_guard_size` must be a power of two of at least 16 bytes.");
+
+ASSERT(__pmp_stack_guard_end__ == __stack_end__, "
+ERROR(riscv-rt): the PMP stack guard must be right below `__stack_end__`.");
+
+/* The harts other than the last guard the bottom of their share of `.stack` */
+ASSERT(_max_hart_id == 0 || (_stack_start % _pmp_stack_guard_size == 0 &&
+    _hart_stack_size % _pmp_stack_guard_size == 0 &&
+    _pmp_stack_guard_size + _stack_guard_size < _hart_stack_size), "
+ERROR(riscv-rt): with `_max_hart_id` > 0, `_stack_start` and `_hart_stack_size` must be multiples of
+`_pmp_stack_guard_size`, which must leave room for `_stack_guard_size` in `_hart_stack_size`.");
@@ -0,0 +1,39 @@
+/* Appended to link.x by build.rs with the `sdram` feature */
+
+SECTIONS
+{
+    /* `#[place(sdram)]` */
+    .sdram.data : ALIGN(4) {
+        __sdram_data_start__ = .;
+        KEEP(*(.sdram.data .sdram.data.*))
+        . = ALIGN(4);
+        __sdram_data_end__ = .;
+    } > SDRAM AT > REGION_RODATA
+
+    __sdram_data_load_addr__ = LOADADDR(.sdram.data);
+
+    .sdram.bss (NOLOAD) : ALIGN(4) {
+        __sdram_bss_start__ = .;
+        KEEP(*(.sdram.bss .sdram.bss.*))
+        . = ALIGN(4);
+        __sdram_bss_end__ = .;
+        /* not initialized */
+        KEEP(*(.sdram .sdram.noinit .sdram.noinit.*))
+    } > SDRAM
+}
+
+SECTIONS
+{
+    .copy_table.sdram : {
+        LONG(__sdram_data_load_addr__) LONG(__sdram_data_start__) LONG(__sdram_data_end__)
+    } > REGION_RODATA
+}
+INSERT AFTER .copy_table;
+
+SECTIONS
+{
+    .zero_table.sdram : {
+        LONG(__sdram_bss_start__) LONG(__sdram_bss_end__)
+    } > REGION_RODATA
+}
+INSERT AFTER .zero_table;
@@ -0,0 +1,167 @@
+//! Frame pointer backtraces, enabled by the `backtrace` feature.
+//!
+//! Requires code built with `-C force-frame-pointers=yes`. Each frame stores the return address at
+//! `fp - 4` and the caller's `fp` at `fp - 8`, the walk stops at the first frame outside of the stack
+//! or not above the previous one.
+//!
+//! ```ignore
+//! #[panic_handler]
+//! fn panic(info: &PanicInfo) -> ! {
+//!     for ra in hpm_riscv_rt::backtrace::Backtrace::capture() {
+//!         defmt::error!("  at {=usize:#010x}", ra);
+//!     }
+//!     loop {}
+//! }
+//! ```
+
+/// Iterator over return addresses, innermost first
+#[derive(Debug, Clone)]
+pub struct Backtrace {
+    fp: usize,
+    bottom: usize,
+    top: usize,
+}
+
+impl Backtrace {
+    /// Backtrace of the caller.
+    #[cfg(target_arch = "riscv32")]
+    #[inline(always)]
+    pub fn capture() -> Self {
+        let fp: usize;
+        unsafe { core::arch::asm!("mv {}, s0", out(reg) fp, options(nomem, nostack)) };
+        Self::from_fp(fp)
+    }
+
+    /// Walk from `fp` within `[__stack_end__, __stack_start__)`, e.g. `TrapFrame::s0`.
+    #[cfg(target_arch = "riscv32")]
+    pub fn from_fp(fp: usize) -> Self {
+        extern "C" {
+            static __stack_start__: u8;
+            static __stack_end__: u8;
+        }
+
+        let top = core::ptr::addr_of!(__stack_start__) as usize;
+        let bottom = core::ptr::addr_of!(__stack_end__) as usize;
+        Self { fp, bottom, top }
+    }
+
+    /// Walk from `fp` within `[bottom, top)`, for stacks other than the main one.
+    ///
+    /// # Safety
+    ///
+    /// `[bottom, top)` must be readable memory.
+    pub unsafe fn with_bounds(fp: usize, bottom: usize, top: usize) -> Self {
+        Self { fp, bottom, top }
+    }
+}
+
+impl Iterator for Backtrace {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        let fp = self.fp;
+        let word = core::mem::size_of::<usize>();
+        if !fp.is_multiple_of(word) || fp < self.bottom + 2 * word || fp > self.top {
+            return None;
+        }
+
+        let (ra, prev_fp) = unsafe {
+            (
+                ((fp - word) as *const usize).read_volatile(),
+                ((fp - 2 * word) as *const usize).read_volatile(),
+            )
+        };
+        if ra == 0 {
+            return None;
+        }
+
+        // the caller's frame is above, anything else ends the walk after this frame
+        self.fp = if prev_fp > fp { prev_fp } else { 0 };
+        Some(ra)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Backtrace;
+
+    const WORD: usize = core::mem::size_of::<usize>();
+    const WORDS: usize = 16;
+
+    /// A synthetic stack, frame pointers are word indices into it
+    struct Stack([usize; WORDS]);
+
+    impl Stack {
+        fn new() -> Self {
+            Self([0; WORDS])
+        }
+
+        fn addr(&self, index: usize) -> usize {
+            self.0.as_ptr() as usize + index * WORD
+        }
+
+        /// Frame at `fp`: the return address below it, the caller's `fp` below that
+        fn frame(&mut self, fp: usize, ra: usize, prev_fp: usize) {
+            self.0[fp - 1] = ra;
+            self.0[fp - 2] = prev_fp;
+        }
+
+        fn walk(&self, fp: usize) -> Vec<usize> {
+            unsafe { Backtrace::with_bounds(fp, self.addr(0), self.addr(WORDS)) }.collect()
+        }
+    }
+
+    #[test]
+    fn walks_the_chain() {
+        let mut stack = Stack::new();
+        stack.frame(4, 0x100, stack.addr(8));
+        stack.frame(8, 0x200, stack.addr(12));
+        stack.frame(12, 0x300, stack.addr(WORDS));
+        stack.frame(WORDS, 0x400, 0);
+
+        assert_eq!(stack.walk(stack.addr(4)), [0x100, 0x200, 0x300, 0x400]);
+    }
+
+    #[test]
+    fn stops_at_ra_zero() {
+        let mut stack = Stack::new();
+        stack.frame(4, 0x100, stack.addr(8));
+        stack.frame(8, 0, stack.addr(12));
+        stack.frame(12, 0x300, 0);
+
+        assert_eq!(stack.walk(stack.addr(4)), [0x100]);
+        assert_eq!(stack.walk(stack.addr(8)), []);
+    }
+
+    #[test]
+    fn stops_out_of_bounds() {
+        let mut stack = Stack::new();
+        stack.frame(4, 0x100, stack.addr(WORDS + 2));
+
+        assert_eq!(stack.walk(stack.addr(4)), [0x100]);
+        // the frame record would be below the stack
+        assert_eq!(stack.walk(stack.addr(1)), []);
+        assert_eq!(stack.walk(stack.addr(WORDS + 2)), []);
+    }
+
+    #[test]
+    fn stops_at_non_increasing_fp() {
+        let mut stack = Stack::new();
+        stack.frame(4, 0x100, stack.addr(8));
+        stack.frame(8, 0x200, stack.addr(8));
+        stack.frame(12, 0x300, stack.addr(4));
+
+        assert_eq!(stack.walk(stack.addr(4)), [0x100, 0x200]);
+        assert_eq!(stack.walk(stack.addr(12)), [0x300]);
+    }
+
+    #[test]
+    fn stops_at_misaligned_fp() {
+        let mut stack = Stack::new();
+        stack.frame(4, 0x100, stack.addr(8) + 2);
+        stack.frame(8, 0x200, 0);
+
+        assert_eq!(stack.walk(stack.addr(4)), [0x100]);
+        assert_eq!(stack.walk(stack.addr(8) + 2), []);
+    }
+}
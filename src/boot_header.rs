@@ -0,0 +1,172 @@
+//! XPI NOR flash boot header for the HPMicro boot ROM.
+//!
+//! The `boot-header` feature emits the boot header and firmware info table into `.boot_header`.
+//! The NOR config option block is emitted by [`nor_cfg_option!`](crate::nor_cfg_option).
+//!
+//! Both sections are placed in `REGION_BOOT_HEADER`, which must start at the beginning of the flash.
+
+use core::arch::global_asm;
+
+/// How the boot ROM probes the flash
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProbeType {
+    SfdpSdr = 0,
+    SfdpDdr = 1,
+    /// 1-4-4 read, 0xEB
+    Read1_4_4 = 2,
+    /// 1-2-2 read, 0xBB
+    Read1_2_2 = 3,
+    HyperFlash1V8 = 4,
+    HyperFlash3V = 5,
+    /// SPI to OPI DDR
+    OctaBusDdr = 6,
+    /// SPI to OPI DDR
+    XccelaDdr = 8,
+    /// SPI to OPI DDR
+    EcoXipDdr = 10,
+}
+
+/// Flash frequency used by the boot ROM
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Frequency {
+    MHz30 = 1,
+    MHz50 = 2,
+    MHz66 = 3,
+    MHz80 = 4,
+    MHz100 = 5,
+    MHz120 = 6,
+    MHz133 = 7,
+    MHz166 = 8,
+    MHz200 = 9,
+}
+
+/// Number of data pads
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IoMode {
+    Single = 0,
+    Dual = 1,
+    Quad = 2,
+    Octal = 3,
+}
+
+/// XPI NOR config option, read by the boot ROM at offset 0x400 of the flash
+#[derive(Clone, Copy, Debug)]
+pub struct NorConfigOption {
+    probe_type: ProbeType,
+    frequency: Frequency,
+    io_mode: IoMode,
+    cmd_io_mode: IoMode,
+    dummy_cycles: u8,
+}
+
+impl NorConfigOption {
+    const TAG: u32 = 0xfcf9;
+
+    /// SFDP SDR probe at 133MHz on a single pad, the default of the C SDK
+    pub const fn new() -> Self {
+        Self {
+            probe_type: ProbeType::SfdpSdr,
+            frequency: Frequency::MHz133,
+            io_mode: IoMode::Single,
+            cmd_io_mode: IoMode::Single,
+            dummy_cycles: 0,
+        }
+    }
+
+    pub const fn probe_type(mut self, probe_type: ProbeType) -> Self {
+        self.probe_type = probe_type;
+        self
+    }
+
+    pub const fn frequency(mut self, frequency: Frequency) -> Self {
+        self.frequency = frequency;
+        self
+    }
+
+    /// Pads used while probing and reading the flash
+    pub const fn io_mode(mut self, io_mode: IoMode) -> Self {
+        self.io_mode = io_mode;
+        self
+    }
+
+    /// Pads used for commands
+    pub const fn cmd_io_mode(mut self, cmd_io_mode: IoMode) -> Self {
+        self.cmd_io_mode = cmd_io_mode;
+        self
+    }
+
+    /// 0 means auto
+    pub const fn dummy_cycles(mut self, dummy_cycles: u8) -> Self {
+        self.dummy_cycles = dummy_cycles;
+        self
+    }
+
+    /// Header and option words as laid out in flash
+    pub const fn to_words(&self) -> [u32; 4] {
+        // one option word follows the header
+        let header = (Self::TAG << 16) | 1;
+        let option0 = (self.frequency as u32)
+            | ((self.dummy_cycles as u32) << 8)
+            | ((self.io_mode as u32) << 20)
+            | ((self.cmd_io_mode as u32) << 24)
+            | ((self.probe_type as u32) << 28);
+        [header, option0, 0, 0]
+    }
+}
+
+impl Default for NorConfigOption {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Emit the NOR config option block into `.nor_cfg_option`.
+///
+/// ```ignore
+/// use hpm_riscv_rt::boot_header::{Frequency, NorConfigOption};
+///
+/// hpm_riscv_rt::nor_cfg_option!(NorConfigOption::new().frequency(Frequency::MHz100));
+/// ```
+#[macro_export]
+macro_rules! nor_cfg_option {
+    ($option:expr) => {
+        #[used]
+        #[no_mangle]
+        #[link_section = ".nor_cfg_option"]
+        static __NOR_CFG_OPTION: [u32; 4] =
+            $crate::boot_header::NorConfigOption::to_words(&$option);
+    };
+}
+
+// boot header, followed by one firmware info table. The symbols are defined in boot-header.x.
+global_asm!(
+    r#"
+    .section .boot_header, "a"
+    .global __boot_header
+__boot_header:
+    .byte 0xbf          // tag
+    .byte 0x10          // version
+    .short 16 + 128     // length, header and firmware info tables
+    .word 0             // flags
+    .short 0            // sw_version
+    .byte 0             // fuse_version
+    .byte 1             // fw_count
+    .short 0            // dc_block_offset
+    .short 0            // sig_block_offset
+
+    // firmware info table
+    .word __app_offset__
+    .word __fw_size__
+    .word 0             // flags
+    .word 0
+    .word __app_load_addr__
+    .word 0
+    .word _start        // entry point
+    .word 0
+    .zero 64            // hash
+    .zero 32            // iv
+    "#,
+);
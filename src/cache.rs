@@ -0,0 +1,127 @@
+//! D-cache maintenance for memory shared with DMA masters.
+//!
+//! Buffers in `.noncacheable.data`/`.noncacheable.bss` need none of this. Others must be written back
+//! before a device reads them, and invalidated after a device wrote them.
+//! Ranges must be aligned to [`CACHE_LINE_SIZE`], otherwise unrelated data sharing a line would be
+//! written back or discarded with them.
+
+use core::ops::{Deref, DerefMut};
+
+use andes_riscv::l1c;
+
+/// L1 D-cache line size of the HPMicro cores
+pub const CACHE_LINE_SIZE: usize = 64;
+
+#[inline]
+#[track_caller]
+fn check_range(addr: usize, size: usize) {
+    assert!(
+        addr.is_multiple_of(CACHE_LINE_SIZE) && size.is_multiple_of(CACHE_LINE_SIZE),
+        "cache maintenance range must be aligned to {} bytes",
+        CACHE_LINE_SIZE
+    );
+}
+
+/// Write back dirty lines of `[addr, addr + size)` to memory, e.g. before a DMA reads it.
+#[track_caller]
+pub fn clean_range(addr: usize, size: usize) {
+    check_range(addr, size);
+    if size != 0 && l1c::dc_is_enabled() {
+        unsafe { l1c::dc_writeback(addr as u32, size as u32) };
+    }
+}
+
+/// Discard the lines of `[addr, addr + size)`, e.g. after a DMA wrote it.
+///
+/// # Safety
+///
+/// Writes not yet written back are lost, and the range must not be referenced while its content changes.
+#[track_caller]
+pub unsafe fn invalidate_range(addr: usize, size: usize) {
+    check_range(addr, size);
+    if size != 0 && l1c::dc_is_enabled() {
+        l1c::dc_invalidate(addr as u32, size as u32);
+    }
+}
+
+/// Write back and discard the lines of `[addr, addr + size)`.
+#[track_caller]
+pub fn clean_invalidate_range(addr: usize, size: usize) {
+    check_range(addr, size);
+    if size != 0 && l1c::dc_is_enabled() {
+        unsafe { l1c::dc_flush(addr as u32, size as u32) };
+    }
+}
+
+/// Cache line aligned buffer for DMA, its size is rounded up to whole cache lines.
+///
+/// ```ignore
+/// static mut RX: DmaBuffer<u8, 256> = DmaBuffer::new([0; 256]);
+///
+/// let rx = unsafe { &mut *core::ptr::addr_of_mut!(RX) };
+/// rx.prepare_rx();
+/// // start the DMA into `rx.as_mut_ptr()`, wait for it
+/// unsafe { rx.complete_rx() };
+/// ```
+#[repr(C, align(64))]
+pub struct DmaBuffer<T, const N: usize> {
+    buf: [T; N],
+}
+
+const _: () = assert!(core::mem::align_of::<DmaBuffer<u8, 1>>() == CACHE_LINE_SIZE);
+
+impl<T, const N: usize> DmaBuffer<T, N> {
+    pub const fn new(buf: [T; N]) -> Self {
+        Self { buf }
+    }
+
+    pub fn as_ptr(&self) -> *const T {
+        self.buf.as_ptr()
+    }
+
+    pub fn as_mut_ptr(&mut self) -> *mut T {
+        self.buf.as_mut_ptr()
+    }
+
+    fn range(&self) -> (usize, usize) {
+        (self as *const Self as usize, core::mem::size_of::<Self>())
+    }
+
+    /// Write back the buffer before a device reads it.
+    pub fn prepare_tx(&self) {
+        let (addr, size) = self.range();
+        clean_range(addr, size);
+    }
+
+    /// Write back and discard the buffer before a device writes it, so no dirty line is evicted
+    /// over the incoming data.
+    pub fn prepare_rx(&mut self) {
+        let (addr, size) = self.range();
+        clean_invalidate_range(addr, size);
+    }
+
+    /// Discard the buffer after a device wrote it, so the CPU reads the new data.
+    ///
+    /// # Safety
+    ///
+    /// The device must have written valid values of `T`, and the buffer must not be written by the CPU
+    /// since [`prepare_rx`](Self::prepare_rx).
+    pub unsafe fn complete_rx(&mut self) {
+        let (addr, size) = self.range();
+        invalidate_range(addr, size);
+    }
+}
+
+impl<T, const N: usize> Deref for DmaBuffer<T, N> {
+    type Target = [T; N];
+
+    fn deref(&self) -> &Self::Target {
+        &self.buf
+    }
+}
+
+impl<T, const N: usize> DerefMut for DmaBuffer<T, N> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.buf
+    }
+}
@@ -0,0 +1,134 @@
+//! Crash records kept across resets, enabled by the `crash-report` feature.
+//!
+//! `DefaultExceptionHandler` saves the trap frame and the top of the stack into a CRC protected
+//! record in `.noinit`, then calls `CrashReset`. Read it back on the next boot with [`take_last`].
+//!
+//! The default `CrashReset` is a PPOR software reset. Provide your own `CrashReset` for parts
+//! with a different PPOR, or to reset another way:
+//!
+//! ```ignore
+//! #[no_mangle]
+//! extern "C" fn CrashReset() -> ! { ... }
+//! ```
+
+use core::mem::MaybeUninit;
+use core::ptr::{addr_of, addr_of_mut};
+
+use crate::trap::TrapFrame;
+
+/// Words of the stack saved from `sp` of the faulting code
+pub const STACK_WORDS: usize = 32;
+
+const MAGIC: u32 = 0x4352_5348; // "CRSH"
+
+/// State at the time of the crash
+#[repr(C)]
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CrashRecord {
+    /// Registers, including `mcause`, `mtval` and `mepc`
+    pub trap_frame: TrapFrame,
+    /// Words from `trap_frame.sp` upwards
+    pub stack: [usize; STACK_WORDS],
+    /// Valid words in `stack`, less than `STACK_WORDS` near the top of the stack
+    pub stack_len: usize,
+}
+
+#[repr(C)]
+struct Stored {
+    magic: u32,
+    record: CrashRecord,
+    crc: u32,
+}
+
+#[link_section = ".noinit.hpm_riscv_rt.crash"]
+static mut STORED: MaybeUninit<Stored> = MaybeUninit::uninit();
+
+/// CRC-32 (IEEE 802.3) of everything but the CRC itself, bitwise to stay small.
+///
+/// Volatile reads, the record may be anything after a cold reset.
+unsafe fn crc32(stored: *const Stored) -> u32 {
+    let bytes = stored as *const u8;
+    let mut crc = !0u32;
+    for i in 0..core::mem::offset_of!(Stored, crc) {
+        crc ^= bytes.add(i).read_volatile() as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+/// Save the crash record, called by `DefaultExceptionHandler`.
+pub(crate) unsafe fn record(trap_frame: &TrapFrame) {
+    let stored = addr_of_mut!(STORED).cast::<Stored>();
+
+    // only read the stack of this hart, `sp` may be corrupted
+    let (top, bottom) = crate::hart_stack();
+    let sp = trap_frame.sp & !0b11;
+    let stack_len = if (bottom..top).contains(&sp) {
+        (top - sp).min(STACK_WORDS * 4) / 4
+    } else {
+        0
+    };
+
+    addr_of_mut!((*stored).magic).write_volatile(MAGIC);
+    addr_of_mut!((*stored).record.trap_frame).copy_from_nonoverlapping(trap_frame, 1);
+    let stack = addr_of_mut!((*stored).record.stack).cast::<usize>();
+    for i in 0..STACK_WORDS {
+        let word = if i < stack_len {
+            (sp as *const usize).add(i).read_volatile()
+        } else {
+            0
+        };
+        stack.add(i).write_volatile(word);
+    }
+    addr_of_mut!((*stored).record.stack_len).write_volatile(stack_len);
+    addr_of_mut!((*stored).crc).write_volatile(crc32(stored));
+
+    // `.noinit` may be in cached RAM, a reset drops dirty lines
+    andes_riscv::l1c::dc_writeback_all();
+}
+
+/// Reset through `CrashReset`.
+pub(crate) fn reset() -> ! {
+    extern "C" {
+        fn CrashReset() -> !;
+    }
+    unsafe { CrashReset() }
+}
+
+/// Default `CrashReset`, a PPOR software reset.
+///
+/// The PPOR is at 0xF40C0000 on HPM6700/6300/6200/5300.
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "C" fn DefaultCrashReset() -> ! {
+    const PPOR: usize = 0xF40C_0000;
+    const RESET_ENABLE: usize = PPOR + 0x0C;
+    const SOFTWARE_RESET: usize = PPOR + 0x1C;
+    unsafe {
+        let enable = (RESET_ENABLE as *mut u32).read_volatile();
+        (RESET_ENABLE as *mut u32).write_volatile(enable | (1 << 31));
+        // reset after this many 24MHz cycles
+        (SOFTWARE_RESET as *mut u32).write_volatile(1000);
+    }
+    loop {
+        // wait for the reset
+        continue;
+    }
+}
+
+/// The record of the last crash, if any. It is cleared, so each crash is returned once.
+pub fn take_last() -> Option<CrashRecord> {
+    unsafe {
+        let stored = addr_of_mut!(STORED).cast::<Stored>();
+        if addr_of!((*stored).magic).read_volatile() != MAGIC
+            || addr_of!((*stored).crc).read_volatile() != crc32(stored)
+        {
+            return None;
+        }
+        addr_of_mut!((*stored).magic).write_volatile(0);
+        Some(addr_of!((*stored).record).read_volatile())
+    }
+}
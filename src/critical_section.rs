@@ -0,0 +1,152 @@
+//! `critical-section` implementations, selected by the `critical-section-*` features.
+
+#[cfg(any(
+    all(
+        feature = "critical-section-single-hart",
+        feature = "critical-section-multi-hart"
+    ),
+    all(
+        feature = "critical-section-single-hart",
+        feature = "critical-section-plic-threshold"
+    ),
+    all(
+        feature = "critical-section-multi-hart",
+        feature = "critical-section-plic-threshold"
+    ),
+))]
+compile_error!("only one of the `critical-section-*` features can be enabled");
+
+use critical_section::{set_impl, Impl, RawRestoreState};
+
+/// Clear `mstatus.MIE`, returns whether it was set.
+#[cfg(any(
+    feature = "critical-section-single-hart",
+    feature = "critical-section-multi-hart"
+))]
+#[inline(always)]
+unsafe fn disable_mie() -> bool {
+    let mstatus: usize;
+    core::arch::asm!("csrrci {}, mstatus, 0b1000", out(reg) mstatus);
+    mstatus & 0b1000 != 0
+}
+
+#[cfg(feature = "critical-section-single-hart")]
+mod single_hart {
+    use andes_riscv::riscv::register::mstatus;
+
+    use super::*;
+
+    /// Disables all interrupts of the current hart.
+    struct SingleHartCriticalSection;
+    set_impl!(SingleHartCriticalSection);
+
+    unsafe impl Impl for SingleHartCriticalSection {
+        unsafe fn acquire() -> RawRestoreState {
+            disable_mie()
+        }
+
+        unsafe fn release(was_active: RawRestoreState) {
+            // Only re-enable interrupts if they were enabled before the critical section.
+            if was_active {
+                mstatus::set_mie();
+            }
+        }
+    }
+}
+
+#[cfg(feature = "critical-section-multi-hart")]
+mod multi_hart {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    use andes_riscv::riscv::register::{mhartid, mstatus};
+
+    use super::*;
+
+    const WAS_ACTIVE: u8 = 0b01;
+    const LOCKED: u8 = 0b10;
+
+    /// `mhartid + 1` of the hart holding the lock, 0 when free.
+    ///
+    /// Lives in `.noncacheable.bss`, link.x checks that it is not in the core local ILM/DLM, of which
+    /// every hart has its own.
+    #[export_name = "__hpm_riscv_rt_lock_owner"]
+    #[link_section = ".noncacheable.bss"]
+    static LOCK_OWNER: AtomicUsize = AtomicUsize::new(0);
+
+    /// Disables all interrupts of the current hart, and takes a spinlock shared by all harts.
+    struct MultiHartCriticalSection;
+    set_impl!(MultiHartCriticalSection);
+
+    unsafe impl Impl for MultiHartCriticalSection {
+        unsafe fn acquire() -> RawRestoreState {
+            let mut state = if disable_mie() { WAS_ACTIVE } else { 0 };
+
+            let me = mhartid::read() + 1;
+            // nested critical section on the same hart, the lock is already held
+            if LOCK_OWNER.load(Ordering::Relaxed) != me {
+                while LOCK_OWNER
+                    .compare_exchange_weak(0, me, Ordering::Acquire, Ordering::Relaxed)
+                    .is_err()
+                {
+                    core::hint::spin_loop();
+                }
+                state |= LOCKED;
+            }
+            state
+        }
+
+        unsafe fn release(state: RawRestoreState) {
+            if state & LOCKED != 0 {
+                LOCK_OWNER.store(0, Ordering::Release);
+            }
+            if state & WAS_ACTIVE != 0 {
+                mstatus::set_mie();
+            }
+        }
+    }
+}
+
+#[cfg(feature = "critical-section-plic-threshold")]
+mod plic_threshold {
+    use core::sync::atomic::{AtomicU32, Ordering};
+
+    use andes_riscv::plic::PlicExt;
+
+    use super::*;
+    use crate::PLIC;
+
+    /// 0 means all external interrupts are masked.
+    static THRESHOLD: AtomicU32 = AtomicU32::new(0);
+
+    /// Set the PLIC priority masked by critical sections.
+    ///
+    /// External interrupts with priority `<= threshold` are masked, higher ones and core local
+    /// interrupts still run, so they must not touch data guarded by critical sections.
+    pub fn set_critical_section_threshold(threshold: u32) {
+        THRESHOLD.store(threshold, Ordering::Relaxed);
+    }
+
+    /// Raises the PLIC threshold of target 0.
+    struct PlicThresholdCriticalSection;
+    set_impl!(PlicThresholdCriticalSection);
+
+    unsafe impl Impl for PlicThresholdCriticalSection {
+        unsafe fn acquire() -> RawRestoreState {
+            let old = PLIC.threshold();
+            let threshold = match THRESHOLD.load(Ordering::Relaxed) {
+                0 => PLIC.info().read().max_priority() as u32,
+                threshold => threshold,
+            };
+            if threshold > old {
+                PLIC.set_threshold(threshold);
+            }
+            old
+        }
+
+        unsafe fn release(old: RawRestoreState) {
+            PLIC.set_threshold(old);
+        }
+    }
+}
+#[cfg(feature = "critical-section-plic-threshold")]
+pub use plic_threshold::set_critical_section_threshold;
@@ -0,0 +1,231 @@
+//! Global allocator over `.heap`, enabled by the `alloc` feature.
+//!
+//! The heap is `[__heap_start__, __heap_end__)`, sized by `_heap_size` in link.x.
+//! It is a first-fit free list sorted by address, freed blocks are merged with their neighbours.
+//! Allocation disables interrupts of the current hart and takes a spinlock, so it may be used from
+//! interrupt handlers.
+//!
+//! Only the boot hart may allocate: the heap state lives in `.bss`, of which every hart has its own
+//! copy when it is core local, and `init` only runs on the boot hart.
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::cell::UnsafeCell;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Granularity of blocks, also the minimal alignment
+const BLOCK: usize = 8;
+
+/// Free block header, stored in the free memory itself
+struct Hole {
+    size: usize,
+    next: *mut Hole,
+}
+
+const _: () = assert!(core::mem::size_of::<Hole>() <= BLOCK);
+
+struct Heap {
+    head: *mut Hole,
+    size: usize,
+    used: usize,
+}
+
+impl Heap {
+    /// Allocate from the first hole that fits, the padding before and the rest after stay holes.
+    unsafe fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        let size = block_size(layout);
+        let align = layout.align().max(BLOCK);
+
+        let mut prev: *mut *mut Hole = &mut self.head;
+        while !(*prev).is_null() {
+            let hole = *prev;
+            let hole_start = hole as usize;
+            let hole_end = hole_start + (*hole).size;
+            let start = align_up(hole_start, align);
+
+            if let Some(end) = start.checked_add(size).filter(|&end| end <= hole_end) {
+                let next = (*hole).next;
+                // rest after the allocation
+                let next = if end < hole_end {
+                    let rest = end as *mut Hole;
+                    rest.write(Hole {
+                        size: hole_end - end,
+                        next,
+                    });
+                    rest
+                } else {
+                    next
+                };
+                // padding before the allocation
+                if start > hole_start {
+                    (*hole).size = start - hole_start;
+                    (*hole).next = next;
+                } else {
+                    *prev = next;
+                }
+
+                self.used += size;
+                return start as *mut u8;
+            }
+            prev = &mut (*hole).next;
+        }
+        ptr::null_mut()
+    }
+
+    /// Put the block back into the list, merging it with adjacent holes.
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        let size = block_size(layout);
+        let start = ptr as usize;
+        self.used -= size;
+
+        // find the holes before and after the block
+        let mut prev: *mut Hole = ptr::null_mut();
+        let mut next = self.head;
+        while !next.is_null() && (next as usize) < start {
+            prev = next;
+            next = (*next).next;
+        }
+
+        let block = ptr as *mut Hole;
+        block.write(Hole { size, next });
+        if !next.is_null() && start + size == next as usize {
+            (*block).size += (*next).size;
+            (*block).next = (*next).next;
+        }
+
+        if prev.is_null() {
+            self.head = block;
+        } else if prev as usize + (*prev).size == start {
+            (*prev).size += (*block).size;
+            (*prev).next = (*block).next;
+        } else {
+            (*prev).next = block;
+        }
+    }
+
+    unsafe fn largest_free_block(&self) -> usize {
+        let mut largest = 0;
+        let mut hole = self.head;
+        while !hole.is_null() {
+            largest = largest.max((*hole).size);
+            hole = (*hole).next;
+        }
+        largest
+    }
+}
+
+fn align_up(addr: usize, align: usize) -> usize {
+    (addr + align - 1) & !(align - 1)
+}
+
+fn block_size(layout: Layout) -> usize {
+    align_up(layout.size().max(1), BLOCK)
+}
+
+struct LockedHeap {
+    locked: AtomicBool,
+    heap: UnsafeCell<Heap>,
+}
+
+unsafe impl Sync for LockedHeap {}
+
+impl LockedHeap {
+    fn with<R>(&self, f: impl FnOnce(&mut Heap) -> R) -> R {
+        let mstatus: usize;
+        // clear `mstatus.MIE`, an interrupt handler allocating would deadlock
+        unsafe { core::arch::asm!("csrrci {}, mstatus, 0b1000", out(reg) mstatus) };
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            core::hint::spin_loop();
+        }
+
+        let r = f(unsafe { &mut *self.heap.get() });
+
+        self.locked.store(false, Ordering::Release);
+        if mstatus & 0b1000 != 0 {
+            unsafe { core::arch::asm!("csrsi mstatus, 0b1000") };
+        }
+        r
+    }
+}
+
+unsafe impl GlobalAlloc for LockedHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.with(|heap| heap.alloc(layout))
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.with(|heap| heap.dealloc(ptr, layout))
+    }
+}
+
+#[global_allocator]
+static HEAP: LockedHeap = LockedHeap {
+    locked: AtomicBool::new(false),
+    heap: UnsafeCell::new(Heap {
+        head: ptr::null_mut(),
+        size: 0,
+        used: 0,
+    }),
+};
+
+/// Called by `_start_rust` after `.bss` is zeroed.
+pub(crate) unsafe fn init() {
+    extern "C" {
+        static __heap_start__: u8;
+        static __heap_end__: u8;
+    }
+
+    let start = align_up(ptr::addr_of!(__heap_start__) as usize, BLOCK);
+    let end = ptr::addr_of!(__heap_end__) as usize & !(BLOCK - 1);
+
+    HEAP.with(|heap| {
+        if end > start {
+            let hole = start as *mut Hole;
+            hole.write(Hole {
+                size: end - start,
+                next: ptr::null_mut(),
+            });
+            heap.head = hole;
+            heap.size = end - start;
+        }
+        heap.used = 0;
+    });
+}
+
+/// Bytes currently allocated, including the rounding of each allocation to 8 bytes
+pub fn used() -> usize {
+    HEAP.with(|heap| heap.used)
+}
+
+/// Bytes currently free, possibly fragmented
+pub fn free() -> usize {
+    HEAP.with(|heap| heap.size - heap.used)
+}
+
+/// Size of the largest free block, the upper bound of the next allocation
+pub fn largest_free_block() -> usize {
+    HEAP.with(|heap| unsafe { heap.largest_free_block() })
+}
+
+#[alloc_error_handler]
+fn alloc_error(layout: Layout) -> ! {
+    extern "Rust" {
+        fn AllocErrorHandler(layout: Layout) -> !;
+    }
+    unsafe { AllocErrorHandler(layout) }
+}
+
+/// Default `AllocErrorHandler`, panics with the failed layout.
+#[no_mangle]
+#[allow(non_snake_case)]
+pub fn DefaultAllocErrorHandler(layout: Layout) -> ! {
+    panic!(
+        "memory allocation of {} bytes (align {}) failed",
+        layout.size(),
+        layout.align()
+    )
+}
@@ -1,16 +1,20 @@
 #![no_std]
 #![feature(abi_riscv_interrupt)]
+#![cfg_attr(feature = "alloc", feature(alloc_error_handler))]
 
 use core::arch::global_asm;
+#[cfg(not(feature = "single-hart"))]
+use core::sync::atomic::{AtomicBool, Ordering};
 
 use andes_riscv::{
+    plic::Plic,
     register::mmisc_ctl,
     riscv::register::{mcounteren, mie, mstatus, mtvec, stvec::TrapMode},
 };
 
-pub use hpm_riscv_rt_macros::{entry, fast, interrupt, pre_init};
-
-pub mod trap;
+pub use hpm_riscv_rt_macros::{
+    entry, exception, fast, hart_entry, interrupt, noinit, noncacheable, place, pre_init, syscall,
+};
 
 /// Parse cfg attributes inside a global_asm call.
 macro_rules! cfg_global_asm {
@@ -31,6 +35,41 @@ macro_rules! cfg_global_asm {
     };
 }
 
+pub mod cache;
+pub mod pmp;
+pub mod trap;
+pub mod umode;
+
+#[cfg(feature = "boot-header")]
+pub mod boot_header;
+
+#[cfg(feature = "stack-paint")]
+pub mod stack;
+
+#[cfg(feature = "alloc")]
+pub mod heap;
+
+#[cfg(feature = "crash-report")]
+pub mod crash;
+
+#[cfg(feature = "backtrace")]
+pub mod backtrace;
+
+#[cfg(feature = "smode")]
+pub mod smode;
+
+#[cfg(feature = "semihosting")]
+pub mod semihosting;
+
+#[cfg(any(
+    feature = "critical-section-single-hart",
+    feature = "critical-section-multi-hart",
+    feature = "critical-section-plic-threshold"
+))]
+mod critical_section;
+#[cfg(feature = "critical-section-plic-threshold")]
+pub use crate::critical_section::set_critical_section_threshold;
+
 //    ".attribute arch, \"rv64im\"",
 cfg_global_asm!(
     // no "c" here, the same as riscv-rt
@@ -42,19 +81,96 @@ _start:
      .option norelax
      la gp, __global_pointer$
      .option pop
+     // 0 while in M-mode, see `CORE_LOCAL`
+     csrw mscratch, zero
     ",
-    "la t1, __stack_safe
-     addi sp, t1, -16
-     call __pre_init
+    // drop the cache contents left by a previous stage, before anything of this image is cached,
+    // and keep the D-cache off until `_start_rust` has set up the PMA
+    #[cfg(feature = "dcache-writeback")]
+    "li t0, 6 // L1D_WBINVAL_ALL
+     csrw 0x7cc, t0 // mcctlcommand
+    ",
+    #[cfg(not(feature = "dcache-writeback"))]
+    "li t0, 23 // L1D_INVAL_ALL
+     csrw 0x7cc, t0 // mcctlcommand
+    ",
+    "csrci 0x7ca, 0b10 // mcache_ctl.DC_EN
+     fence.i
+    ",
+    // park harts beyond `_max_hart_id`, s1 = hartid * _hart_stack_size
+    #[cfg(not(feature = "single-hart"))]
+    "csrr a0, mhartid
+     lui t0, %hi(_max_hart_id)
+     addi t0, t0, %lo(_max_hart_id)
+     bgtu a0, t0, 3f
+     lui t0, %hi(_hart_stack_size)
+     addi t0, t0, %lo(_hart_stack_size)
+     mul s1, a0, t0
+    ",
+    "la t1, __stack_safe",
+    #[cfg(not(feature = "single-hart"))]
+    "sub t1, t1, s1",
+    "addi sp, t1, -16",
+    // paint the stack of this hart below sp with `stack::PAINT`, t1 = bottom of the stack
+    #[cfg(feature = "stack-paint")]
+    "la t1, __stack_end__",
+    #[cfg(all(feature = "stack-paint", not(feature = "single-hart")))]
+    "csrr a0, mhartid
+     lui t0, %hi(_max_hart_id)
+     addi t0, t0, %lo(_max_hart_id)
+     beq a0, t0, 4f
+     la t1, __stack_start__
+     sub t1, t1, s1
+     lui t0, %hi(_hart_stack_size)
+     addi t0, t0, %lo(_hart_stack_size)
+     sub t1, t1, t0
+4:
+    ",
+    #[cfg(feature = "stack-paint")]
+    "li t0, 0xc5c5c5c5
+     bgeu t1, sp, 6f
+5:
+     sw t0, 0(t1)
+     addi t1, t1, 4
+     bltu t1, sp, 5b
+6:
+    ",
+    // only the hart chosen by `_mp_hook` runs `__pre_init` and initializes memory
+    #[cfg(not(feature = "single-hart"))]
+    "csrr a0, mhartid
+     call _mp_hook
+     beqz a0, 2f
+    ",
+    // boot hart, drop the release of other harts left in RAM by a previous boot
+    #[cfg(not(feature = "single-hart"))]
+    "la t0, __hpm_riscv_rt_memory_ready
+     sw zero, 0(t0)
+    ",
+    "call __pre_init
     ",
     // set sp
-    "la t1, __stack_start__
-     addi sp, t1, -16",
+    "la t1, __stack_start__",
+    #[cfg(not(feature = "single-hart"))]
+    "sub t1, t1, s1",
+    "addi sp, t1, -16",
     "call _start_rust",
     "
 1:
     j 1b
     ",
+    // other harts, wait for memory to be ready in `_start_rust_hart`
+    #[cfg(not(feature = "single-hart"))]
+    "
+2:
+    la t1, __stack_start__
+    sub t1, t1, s1
+    addi sp, t1, -16
+    csrr a0, mhartid
+    call _start_rust_hart
+3:
+    wfi
+    j 3b
+    ",
 );
 
 // weak functions
@@ -65,37 +181,102 @@ __pre_init:
     #[cfg(not(feature = "single-hart"))]
     ".weak _mp_hook
 _mp_hook:
-    beqz a0, 2f // if hartid is 0, return true
-1:  wfi // Otherwise, wait for interrupt in a loop
-    j 1b
-2:  li a0, 1
+    seqz a0, a0 // return true only if hartid is 0
     ret",
+    #[cfg(not(feature = "single-hart"))]
+    ".weak hart_main
+hart_main:
+1:  wfi // no `#[hart_entry]`, wait for interrupt in a loop
+    j 1b",
 );
 
+/// Set by the boot hart once `.data`/`.bss` are initialized, cleared by `_start` before that.
+///
+/// Lives in `.noncacheable.bss`, link.x checks that `REGION_NONCACHEABLE_RAM` is shared by all harts.
+#[cfg(not(feature = "single-hart"))]
+#[export_name = "__hpm_riscv_rt_memory_ready"]
+#[link_section = ".noncacheable.bss"]
+static MEMORY_READY: AtomicBool = AtomicBool::new(false);
+
+/// End of the core local ILM/DLM windows, each hart sees its own memories below this address.
+const CORE_LOCAL_END: usize = 0x0100_0000;
+
+pub(crate) const PLIC: Plic = unsafe { Plic::from_ptr(0xE4000000 as *mut ()) };
+
+/// PLIC configuration of an external interrupt, generated by `#[interrupt(priority = N, enable)]`
+#[doc(hidden)]
+#[repr(C)]
+pub struct InterruptConfig {
+    pub irq: u16,
+    pub priority: u32,
+    pub enable: bool,
+    /// enabled for the S-mode context, PLIC target 1
+    pub supervisor: bool,
+}
+
+/// PLIC bring-up, run once by the boot hart, then the interrupt setup of the boot hart itself.
 #[no_mangle]
 unsafe extern "C" fn _setup_interrupts() {
-    use andes_riscv::plic::{Plic, PlicExt};
+    use andes_riscv::plic::PlicExt;
+
+    // clean up plic, it will help while debugging.
+    // Target 0 is the M-mode context, target 1 the S-mode context.
+    PLIC.set_threshold(0);
+    PLIC.targetconfig(1)
+        .threshold()
+        .write(|w| w.set_threshold(0));
+    for target in 0..2 {
+        for i in 0..1024 {
+            PLIC.targetconfig(target)
+                .claim()
+                .modify(|w| w.set_interrupt_id(i));
+        }
+        // clear any bits left in plic enable register
+        for i in 0..4 {
+            PLIC.targetint(target).inten(i).write(|w| w.0 = 0);
+        }
+    }
+
+    // apply `#[interrupt(priority = N, enable)]`
+    {
+        extern "C" {
+            static __interrupt_config_start__: InterruptConfig;
+            static __interrupt_config_end__: InterruptConfig;
+        }
+
+        let start = core::ptr::addr_of!(__interrupt_config_start__);
+        let end = core::ptr::addr_of!(__interrupt_config_end__);
+        let configs = core::slice::from_raw_parts(start, end.offset_from(start) as usize);
+
+        for config in configs {
+            let irq = config.irq as usize;
+            PLIC.priority(irq - 1)
+                .write(|w| w.set_priority(config.priority));
+            if config.enable {
+                PLIC.targetint(config.supervisor as usize)
+                    .inten(irq / 32)
+                    .modify(|w| w.0 |= 1 << (irq % 32));
+            }
+        }
+    }
 
+    PLIC.feature().modify(|w| w.set_vectored(true));
+    #[cfg(feature = "nested-interrupts")]
+    PLIC.feature().modify(|w| w.set_preempt(true));
+
+    setup_hart_interrupts();
+}
+
+/// Interrupt setup of the current hart: the vector table, vectored mode and the interrupt enables.
+///
+/// The PLIC is shared, it is left to `_setup_interrupts` on the boot hart.
+unsafe fn setup_hart_interrupts() {
     extern "C" {
         // Symbol defined in hpm-metapac.
         // The symbol must be in FLASH(XPI) or ILM section.
         static __VECTORED_INTERRUPTS: [u32; 1];
     }
 
-    const PLIC: Plic = unsafe { Plic::from_ptr(0xE4000000 as *mut ()) };
-
-    // clean up plic, it will help while debugging
-    PLIC.set_threshold(0);
-    for i in 0..1024 {
-        PLIC.targetconfig(0)
-            .claim()
-            .modify(|w| w.set_interrupt_id(i));
-    }
-    // clear any bits left in plic enable register
-    for i in 0..4 {
-        PLIC.targetint(0).inten(i).write(|w| w.0 = 0);
-    }
-
     // enable mcycle
     mcounteren::set_cy();
 
@@ -105,7 +286,6 @@ unsafe extern "C" fn _setup_interrupts() {
 
     // Enable vectored external PLIC interrupt
     {
-        PLIC.feature().modify(|w| w.set_vectored(true));
         // CSR_MMISC_CTL = 0x7D0
         // asm!("csrsi 0x7D0, 2");
         mmisc_ctl().modify(|w| w.set_vec_plic(true));
@@ -116,42 +296,154 @@ unsafe extern "C" fn _setup_interrupts() {
     }
 }
 
-#[no_mangle]
-unsafe extern "C" fn _start_rust() -> ! {
-    andes_riscv::l1c::ic_enable();
-    andes_riscv::l1c::dc_enable();
-    andes_riscv::l1c::dc_invalidate_all();
+/// Mark `.noncacheable.data` and `.noncacheable.bss` as non-cacheable with PMA entry 0.
+///
+/// link.x aligns `.noncacheable.data` to `__noncacheable_napot_size__` and asserts the window covers
+/// `.noncacheable.bss` and stays in REGION_NONCACHEABLE_RAM.
+unsafe fn setup_noncacheable_pma() {
+    use andes_riscv::register::{
+        pmaaddr0, pmacfg0,
+        vals::{EntryType, MemoryType},
+    };
 
     extern "C" {
-        fn main() -> !;
+        static __noncacheable_data_start__: u8;
+        static __noncacheable_bss_end__: u8;
+    }
+
+    let start = core::ptr::addr_of!(__noncacheable_data_start__) as usize;
+    let end = core::ptr::addr_of!(__noncacheable_bss_end__) as usize;
+    if start == end {
+        return;
     }
 
+    let size: usize;
+    // absolute symbol, out of range of `la`
     core::arch::asm!(
-        "
-        la      {start}, __vector_ram_start__
-        la      {end}, __vector_ram_end__
-        la      {input}, __vector_load_addr__
+        "lui {0}, %hi(__noncacheable_napot_size__)",
+        "addi {0}, {0}, %lo(__noncacheable_napot_size__)",
+        out(reg) size,
+        options(pure, nomem, nostack),
+    );
 
-        bgeu    {start},{end},2f
-    1:
-        lw      {a},0({input})
-        addi    {input},{input},4
-        sw      {a},0({start})
-        addi    {start},{start},4
-        bltu    {start},{end},1b
-    2:
-        ",
-        start = out(reg) _,
-        end = out(reg) _,
-        input = out(reg) _,
-        a = out(reg) _,
+    pmaaddr0().write_value(((start | (size / 2 - 1)) >> 2) as u32);
+    pmacfg0().modify(|w| {
+        w.set_etyp(0, EntryType::NAPOT);
+        w.set_mtyp(0, MemoryType::MEM_NON_CACHE_BUF);
+    });
+}
+
+/// `(top, bottom)` of the stack of the current hart.
+///
+/// Same split as `_start`, the last hart owns the rest of `.stack`. With `pmp-default`, the PMP
+/// guard of the other harts takes the lowest `_pmp_stack_guard_size` bytes of their share.
+#[cfg(any(
+    feature = "stack-protection",
+    feature = "stack-paint",
+    feature = "crash-report",
+    feature = "pmp-default"
+))]
+pub(crate) fn hart_stack() -> (usize, usize) {
+    extern "C" {
+        static __stack_start__: u8;
+        static __stack_end__: u8;
+    }
+
+    let stack_start = core::ptr::addr_of!(__stack_start__) as usize;
+    let stack_end = core::ptr::addr_of!(__stack_end__) as usize;
+
+    #[cfg(feature = "single-hart")]
+    return (stack_start, stack_end);
+
+    #[cfg(not(feature = "single-hart"))]
+    {
+        let (max_hart_id, hart_stack_size): (usize, usize);
+        // absolute symbols, out of range of `la`
+        unsafe {
+            core::arch::asm!(
+                "lui {0}, %hi(_max_hart_id)",
+                "addi {0}, {0}, %lo(_max_hart_id)",
+                "lui {1}, %hi(_hart_stack_size)",
+                "addi {1}, {1}, %lo(_hart_stack_size)",
+                out(reg) max_hart_id,
+                out(reg) hart_stack_size,
+                options(pure, nomem, nostack),
+            );
+        }
+        #[cfg(feature = "pmp-default")]
+        let pmp_guard_size: usize = unsafe {
+            let size;
+            core::arch::asm!(
+                "lui {0}, %hi(_pmp_stack_guard_size)",
+                "addi {0}, {0}, %lo(_pmp_stack_guard_size)",
+                out(reg) size,
+                options(pure, nomem, nostack),
+            );
+            size
+        };
+        #[cfg(not(feature = "pmp-default"))]
+        let pmp_guard_size = 0;
+
+        let hartid = andes_riscv::riscv::register::mhartid::read();
+        let top = stack_start - hartid * hart_stack_size;
+        if hartid == max_hart_id {
+            (top, stack_end)
+        } else {
+            (top, top - hart_stack_size + pmp_guard_size)
+        }
+    }
+}
+
+/// Enable the Andes hardware stack protection on the stack of the current hart.
+///
+/// `msp_bound` is placed `_stack_guard_size` above the bottom of the stack, so the
+/// `StackOverflow` handler still has room to run on the overflowed stack.
+#[cfg(feature = "stack-protection")]
+unsafe fn setup_stack_protection() {
+    use andes_riscv::register::{mhsp_ctl, msp_base, msp_bound};
+
+    let guard_size: usize;
+    core::arch::asm!(
+        "lui {0}, %hi(_stack_guard_size)",
+        "addi {0}, {0}, %lo(_stack_guard_size)",
+        out(reg) guard_size,
     );
 
+    let (top, bottom) = hart_stack();
+
+    msp_base().write_value(top as u32);
+    msp_bound().write_value((bottom + guard_size) as u32);
+    mhsp_ctl().write(|w| {
+        w.set_ovf_en(true);
+        w.set_m(true);
+    });
+}
+
+/// Enable the L1 caches unless disabled by the `no-icache` / `no-dcache` features.
+///
+/// `_start` has already invalidated (or, with `dcache-writeback`, flushed) the D-cache and left it
+/// disabled, so no stale line can be hit once it is enabled here.
+#[inline(always)]
+unsafe fn setup_caches() {
+    #[cfg(not(feature = "no-icache"))]
+    andes_riscv::l1c::ic_enable();
+    #[cfg(feature = "no-icache")]
+    andes_riscv::l1c::ic_disable();
+
+    #[cfg(not(feature = "no-dcache"))]
+    andes_riscv::l1c::dc_enable();
+}
+
+/// Copy `.vectors` and `.fast` into REGION_FASTTEXT.
+///
+/// ILM is core-local on multi-core parts, so every hart loads its own copy.
+#[inline(always)]
+unsafe fn copy_fast_text() {
     core::arch::asm!(
         "
-        la      {start}, __data_start__
-        la      {end}, __data_end__
-        la      {input}, __data_load_addr__
+        la      {start}, __vector_ram_start__
+        la      {end}, __vector_ram_end__
+        la      {input}, __vector_load_addr__
 
         bgeu    {start},{end},2f
     1:
@@ -188,98 +480,105 @@ unsafe extern "C" fn _start_rust() -> ! {
         input = out(reg) _,
         a = out(reg) _,
     );
+}
 
-    core::arch::asm!(
-        "
-        la      {start}, __fast_data_start__
-        la      {end}, __fast_data_end__
-        la      {input}, __fast_data_load_addr__
+/// Record of `__copy_table`, emitted by link.x
+#[repr(C)]
+struct CopyRecord {
+    load: *const u32,
+    start: *mut u32,
+    end: *mut u32,
+}
 
-        bgeu    {start},{end},2f
-    1:
-        lw      {a},0({input})
-        addi    {input},{input},4
-        sw      {a},0({start})
-        addi    {start},{start},4
-        bltu    {start},{end},1b
-    2:
-        ",
-        start = out(reg) _,
-        end = out(reg) _,
-        input = out(reg) _,
-        a = out(reg) _,
-    );
+/// Record of `__zero_table`, emitted by link.x
+#[repr(C)]
+struct ZeroRecord {
+    start: *mut u32,
+    end: *mut u32,
+}
 
-    core::arch::asm!(
-        "
-        la      {start}, __noncacheable_data_start__
-        la      {end}, __noncacheable_data_end__
-        la      {input}, __noncacheable_data_load_addr__
+/// Copy and zero the sections listed in `__copy_table` and `__zero_table`, with `core_local_only`
+/// those in the core local ILM/DLM windows.
+///
+/// Word by word with volatile accesses, the statics being initialized must not be touched
+/// through references before this is done.
+#[inline(always)]
+unsafe fn init_sections(core_local_only: bool) {
+    extern "C" {
+        static __copy_table_start__: CopyRecord;
+        static __copy_table_end__: CopyRecord;
+        static __zero_table_start__: ZeroRecord;
+        static __zero_table_end__: ZeroRecord;
+    }
 
-        bgeu    {start},{end},2f
-    1:
-        lw      {a},0({input})
-        addi    {input},{input},4
-        sw      {a},0({start})
-        addi    {start},{start},4
-        bltu    {start},{end},1b
-    2:
-        ",
-        start = out(reg) _,
-        end = out(reg) _,
-        input = out(reg) _,
-        a = out(reg) _,
-    );
+    let mut record = core::ptr::addr_of!(__copy_table_start__);
+    while record < core::ptr::addr_of!(__copy_table_end__) {
+        let CopyRecord {
+            mut load,
+            mut start,
+            end,
+        } = record.read();
+        if core_local_only && !is_core_local(start as usize) {
+            start = end;
+        }
+        while start < end {
+            start.write_volatile(load.read_volatile());
+            load = load.add(1);
+            start = start.add(1);
+        }
+        record = record.add(1);
+    }
 
-    // zero-out the bss section
+    let mut record = core::ptr::addr_of!(__zero_table_start__);
+    while record < core::ptr::addr_of!(__zero_table_end__) {
+        let ZeroRecord { mut start, end } = record.read();
+        if core_local_only && !is_core_local(start as usize) {
+            start = end;
+        }
+        while start < end {
+            start.write_volatile(0);
+            start = start.add(1);
+        }
+        record = record.add(1);
+    }
+}
 
-    core::arch::asm!(
-        "
-        la      {start}, __bss_start__
-        la      {end}, __bss_end__
+/// Whether `addr` is in the core local ILM/DLM windows, so every hart has its own copy.
+#[inline(always)]
+fn is_core_local(addr: usize) -> bool {
+    addr < CORE_LOCAL_END
+}
 
-        bgeu    {start},{end},2f
-    1:
-        sw      zero,0({start})
-        addi    {start},{start},4
-        bltu    {start},{end},1b
-    2:
-        ",
-        start = out(reg) _,
-        end = out(reg) _,
-    );
+#[no_mangle]
+unsafe extern "C" fn _start_rust() -> ! {
+    #[cfg(feature = "stack-protection")]
+    setup_stack_protection();
 
-    core::arch::asm!(
-        "
-        la      {start}, __fast_bss_start__
-        la      {end}, __fast_bss_end__
+    #[cfg(feature = "pmp-default")]
+    pmp::setup_default();
 
-        bgeu    {start},{end},2f
-    1:
-        sw      zero,0({start})
-        addi    {start},{start},4
-        bltu    {start},{end},1b
-    2:
-        ",
-        start = out(reg) _,
-        end = out(reg) _,
-    );
+    // must be done before the D-cache is enabled
+    setup_noncacheable_pma();
 
-    core::arch::asm!(
-        "
-        la      {start}, __noncacheable_bss_start__
-        la      {end}, __noncacheable_bss_end__
+    setup_caches();
 
-        bgeu    {start},{end},2f
-    1:
-        sw      zero,0({start})
-        addi    {start},{start},4
-        bltu    {start},{end},1b
-    2:
-        ",
-        start = out(reg) _,
-        end = out(reg) _,
-    );
+    extern "C" {
+        fn main() -> !;
+    }
+
+    copy_fast_text();
+
+    init_sections(false);
+
+    #[cfg(feature = "alloc")]
+    heap::init();
+
+    // the L1 caches are not coherent, other harts read memory
+    #[cfg(not(feature = "single-hart"))]
+    {
+        andes_riscv::l1c::dc_writeback_all();
+        MEMORY_READY.store(true, Ordering::Release);
+    }
 
     _setup_interrupts();
 
@@ -289,3 +588,37 @@ unsafe extern "C" fn _start_rust() -> ! {
 
     main()
 }
+
+/// Entry of the harts for which `_mp_hook` returned false.
+#[cfg(not(feature = "single-hart"))]
+#[no_mangle]
+unsafe extern "C" fn _start_rust_hart(hartid: usize) -> ! {
+    extern "C" {
+        fn hart_main(hartid: usize) -> !;
+    }
+
+    while !MEMORY_READY.load(Ordering::Acquire) {
+        core::hint::spin_loop();
+    }
+
+    #[cfg(feature = "stack-protection")]
+    setup_stack_protection();
+    #[cfg(feature = "pmp-default")]
+    pmp::setup_default();
+    setup_noncacheable_pma();
+
+    setup_caches();
+
+    copy_fast_text();
+
+    // `.data`/`.bss` in this hart's own DLM, the boot hart only initialized its DLM
+    init_sections(true);
+
+    setup_hart_interrupts();
+
+    // enable FPU
+    mstatus::set_fs(mstatus::FS::Clean);
+    mstatus::set_fs(mstatus::FS::Initial);
+
+    hart_main(hartid)
+}
@@ -0,0 +1,203 @@
+//! Physical memory protection.
+//!
+//! The lowest numbered entry matching an access decides. Accesses of U-mode code matching no entry
+//! fail, M-mode accesses matching no entry succeed. Locked entries apply to M-mode too, and can only
+//! be changed by a reset.
+//!
+//! Entries used by the runtime:
+//!
+//! | Entries | Region | Permission | Set by |
+//! |---|---|---|---|
+//! | 0 | stack guard of the hart | none, locked | `pmp-default` |
+//! | 1, 2 | `.text` | R X, locked with `pmp-default` | `pmp-default`, [`umode::enter`] |
+//! | 3, 4 | `.rodata` | R, locked with `pmp-default` | `pmp-default`, [`umode::enter`] |
+//! | 5, 6 | `user` sections | R W | [`umode::enter`] |
+//! | 7, 8 | `.data` | R W, locked | `pmp-default` |
+//! | 9, 10 | `.bss` | R W, locked | `pmp-default` |
+//! | 11 | everything else, for S-mode | R W X | `smode::enter` |
+//! | 12 to 15 | free, e.g. peripherals of U-mode code | | |
+//!
+//! The locked `.data` and `.bss` entries come before entry 11, so M-mode cannot execute them after
+//! `smode::enter`. Locked entries apply to U-mode as well, they would give U-mode code access to
+//! `.data` and `.bss`, so [`umode::enter`] panics with `pmp-default`.
+//!
+//! Every hart guards the bottom of its own stack: the last hart the `_pmp_stack_guard_size` bytes
+//! below `__stack_end__`, the other harts the lowest `_pmp_stack_guard_size` bytes of their share of
+//! `.stack`.
+//!
+//! [`umode::enter`]: crate::umode::enter
+
+use core::arch::asm;
+
+/// Number of PMP entries
+pub const ENTRIES: usize = 16;
+
+/// Access permission of an entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Permission(u8);
+
+impl Permission {
+    pub const NONE: Self = Self(0b000);
+    pub const R: Self = Self(0b001);
+    pub const RW: Self = Self(0b011);
+    pub const RX: Self = Self(0b101);
+    pub const RWX: Self = Self(0b111);
+}
+
+/// Addresses matched by an entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Range {
+    /// Matches nothing, the address is the bottom of a `Tor` entry after it
+    Off(usize),
+    /// From the address of the previous entry (0 for entry 0) up to this one, both 4 byte aligned
+    Tor(usize),
+    /// The 4 bytes at the address
+    Na4(usize),
+    /// `size` bytes at `base`, `size` is a power of two of at least 8 and `base` is aligned to it
+    Napot { base: usize, size: usize },
+    /// The whole address space
+    All,
+}
+
+impl Range {
+    /// `pmpaddr` and the `A` field of `pmpcfg`
+    fn encode(self) -> (usize, u8) {
+        match self {
+            Range::Off(addr) => (addr >> 2, 0),
+            Range::Tor(addr) => (addr >> 2, 1),
+            Range::Na4(addr) => (addr >> 2, 2),
+            Range::Napot { base, size } => {
+                assert!(
+                    size.is_power_of_two() && size >= 8 && base.is_multiple_of(size),
+                    "NAPOT size must be a power of two of at least 8, the base aligned to it"
+                );
+                ((base | (size / 2 - 1)) >> 2, 3)
+            }
+            Range::All => (usize::MAX, 3),
+        }
+    }
+}
+
+unsafe fn write_addr(index: usize, addr: usize) {
+    macro_rules! w {
+        ($($n:literal),*) => {
+            match index {
+                $($n => asm!(concat!("csrw pmpaddr", $n, ", {}"), in(reg) addr),)*
+                _ => panic!("PMP index out of range"),
+            }
+        };
+    }
+    w!(0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15);
+}
+
+unsafe fn read_cfg(reg: usize) -> usize {
+    let cfg: usize;
+    match reg {
+        0 => asm!("csrr {}, pmpcfg0", out(reg) cfg),
+        1 => asm!("csrr {}, pmpcfg1", out(reg) cfg),
+        2 => asm!("csrr {}, pmpcfg2", out(reg) cfg),
+        _ => asm!("csrr {}, pmpcfg3", out(reg) cfg),
+    }
+    cfg
+}
+
+unsafe fn write_cfg(reg: usize, cfg: usize) {
+    match reg {
+        0 => asm!("csrw pmpcfg0, {}", in(reg) cfg),
+        1 => asm!("csrw pmpcfg1, {}", in(reg) cfg),
+        2 => asm!("csrw pmpcfg2, {}", in(reg) cfg),
+        _ => asm!("csrw pmpcfg3, {}", in(reg) cfg),
+    }
+}
+
+/// `pmpcfg` byte of entry `index`
+fn cfg(index: usize) -> u8 {
+    assert!(index < ENTRIES, "PMP index out of range");
+    (unsafe { read_cfg(index / 4) } >> (8 * (index % 4))) as u8
+}
+
+/// Whether entry `index` is locked, writes to it are ignored until reset.
+pub fn is_locked(index: usize) -> bool {
+    cfg(index) & 0x80 != 0
+}
+
+/// Configure entry `index`, locking it applies it to M-mode too.
+///
+/// # Safety
+///
+/// Accesses of running code may start to fault, locked entries can only be undone by a reset.
+pub unsafe fn set(index: usize, range: Range, permission: Permission, locked: bool) {
+    assert!(index < ENTRIES, "PMP index out of range");
+    let (addr, a) = range.encode();
+    let byte = permission.0 | a << 3 | (locked as u8) << 7;
+
+    // turn the entry off while its address changes
+    let (reg, shift) = (index / 4, 8 * (index % 4));
+    let others = read_cfg(reg) & !(0xff << shift);
+    write_cfg(reg, others);
+    write_addr(index, addr);
+    write_cfg(reg, others | (byte as usize) << shift);
+}
+
+/// Configure entries `index` and `index + 1` for `[start, end)`, as an `Off` and a `Tor` entry.
+///
+/// # Safety
+///
+/// See [`set`].
+pub unsafe fn set_region(
+    index: usize,
+    start: usize,
+    end: usize,
+    permission: Permission,
+    locked: bool,
+) {
+    set(index, Range::Off(start), Permission::NONE, false);
+    set(index + 1, Range::Tor(end), permission, locked);
+}
+
+/// Lock the default entries, called by `_start_rust` and `_start_rust_hart` with `pmp-default`.
+#[cfg(feature = "pmp-default")]
+pub(crate) unsafe fn setup_default() {
+    use core::ptr::addr_of;
+
+    extern "C" {
+        static __text_start__: u8;
+        static __text_end__: u8;
+        static __rodata_start__: u8;
+        static __rodata_end__: u8;
+        static __data_start__: u8;
+        static __data_end__: u8;
+        static __bss_start__: u8;
+        static __bss_end__: u8;
+    }
+
+    let guard_size: usize;
+    asm!(
+        "lui {0}, %hi(_pmp_stack_guard_size)",
+        "addi {0}, {0}, %lo(_pmp_stack_guard_size)",
+        out(reg) guard_size,
+    );
+    // right below the stack of this hart, `__pmp_stack_guard_start__` for the last one
+    let (_, bottom) = crate::hart_stack();
+    set(
+        0,
+        Range::Napot {
+            base: bottom - guard_size,
+            size: guard_size,
+        },
+        Permission::NONE,
+        true,
+    );
+
+    let range = |start: *const u8, end: *const u8| (start as usize, end as usize);
+    let (start, end) = range(addr_of!(__text_start__), addr_of!(__text_end__));
+    set_region(1, start, end, Permission::RX, true);
+    let (start, end) = range(addr_of!(__rodata_start__), addr_of!(__rodata_end__));
+    set_region(3, start, end, Permission::R, true);
+    let (start, end) = range(addr_of!(__data_start__), addr_of!(__data_end__));
+    set_region(7, start, end, Permission::RW, true);
+    let (start, end) = range(addr_of!(__bss_start__), addr_of!(__bss_end__));
+    set_region(9, start, end, Permission::RW, true);
+}
@@ -0,0 +1,131 @@
+//! Semihosting console and exit, enabled by the `semihosting` feature.
+//!
+//! Requests are the RISC-V `slli x0, x0, 0x1f; ebreak; srai x0, x0, 7` sequence, served by a debugger
+//! with `ebreakm` set or by an emulator (e.g. `qemu-system-riscv32 -semihosting`). Without either, the
+//! `ebreak` traps to `Breakpoint`, whose default handler recognises the sequence and fails the request
+//! with -1 instead of hanging.
+//!
+//! ```ignore
+//! hpm_riscv_rt::hprintln!("tests passed: {}", n);
+//! hpm_riscv_rt::semihosting::exit(0);
+//! ```
+
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+const SYS_OPEN: usize = 0x01;
+const SYS_WRITE: usize = 0x05;
+const SYS_EXIT: usize = 0x18;
+const SYS_EXIT_EXTENDED: usize = 0x20;
+
+const ADP_STOPPED_RUN_TIME_ERROR_UNKNOWN: usize = 0x20023;
+const ADP_STOPPED_APPLICATION_EXIT: usize = 0x20026;
+
+/// Issue semihosting request `op` with the parameter `arg`, usually a pointer to a parameter block.
+///
+/// # Safety
+///
+/// `arg` must be valid for `op`, the host reads and writes memory through it.
+#[inline(never)]
+pub unsafe fn syscall(op: usize, arg: usize) -> usize {
+    let ret;
+    // the host checks the instructions around `ebreak`, they must not be compressed
+    core::arch::asm!(
+        ".balign 16",
+        ".option push",
+        ".option norvc",
+        "slli x0, x0, 0x1f",
+        "ebreak",
+        "srai x0, x0, 7",
+        ".option pop",
+        inout("a0") op => ret,
+        in("a1") arg,
+        options(nostack),
+    );
+    ret
+}
+
+/// Handle of the host console, 0 until opened
+static STDOUT: AtomicUsize = AtomicUsize::new(0);
+
+fn stdout() -> Option<usize> {
+    let handle = STDOUT.load(Ordering::Relaxed);
+    if handle != 0 {
+        return Some(handle);
+    }
+
+    // ":tt" opened for writing ("w" is mode 4) is the console
+    let name = b":tt\0";
+    let args = [name.as_ptr() as usize, 4, name.len() - 1];
+    let handle = unsafe { syscall(SYS_OPEN, args.as_ptr() as usize) };
+    if handle == usize::MAX {
+        return None;
+    }
+    // stored +1, the host may return handle 0
+    STDOUT.store(handle + 1, Ordering::Relaxed);
+    Some(handle + 1)
+}
+
+/// Host console, written by [`hprint!`](crate::hprint) and [`hprintln!`](crate::hprintln)
+pub struct HostStdout;
+
+impl fmt::Write for HostStdout {
+    /// Fails without a host.
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let handle = stdout().ok_or(fmt::Error)? - 1;
+        let mut bytes = s.as_bytes();
+        while !bytes.is_empty() {
+            let args = [handle, bytes.as_ptr() as usize, bytes.len()];
+            // bytes not written
+            let rest = unsafe { syscall(SYS_WRITE, args.as_ptr() as usize) };
+            if rest == usize::MAX || rest >= bytes.len() {
+                return Err(fmt::Error);
+            }
+            bytes = &bytes[bytes.len() - rest..];
+        }
+        Ok(())
+    }
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    let _ = fmt::Write::write_fmt(&mut HostStdout, args);
+}
+
+/// Print to the host console, output is dropped without a host.
+#[macro_export]
+macro_rules! hprint {
+    ($($arg:tt)*) => {
+        $crate::semihosting::_print(format_args!($($arg)*))
+    };
+}
+
+/// Print a line to the host console, output is dropped without a host.
+#[macro_export]
+macro_rules! hprintln {
+    () => {
+        $crate::semihosting::_print(format_args!("\n"))
+    };
+    ($fmt:literal $($arg:tt)*) => {
+        $crate::semihosting::_print(format_args!(concat!($fmt, "\n") $($arg)*))
+    };
+}
+
+/// End the session with exit status `code`, e.g. to report a test run. Hangs without a host.
+pub fn exit(code: i32) -> ! {
+    let args = [ADP_STOPPED_APPLICATION_EXIT, code as usize];
+    unsafe {
+        syscall(SYS_EXIT_EXTENDED, args.as_ptr() as usize);
+        // hosts without SYS_EXIT_EXTENDED, only success or failure
+        let reason = if code == 0 {
+            ADP_STOPPED_APPLICATION_EXIT
+        } else {
+            ADP_STOPPED_RUN_TIME_ERROR_UNKNOWN
+        };
+        syscall(SYS_EXIT, reason);
+    }
+    loop {
+        // no host
+        continue;
+    }
+}
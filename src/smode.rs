@@ -0,0 +1,119 @@
+//! Running a supervisor kernel in S-mode, enabled by the `smode` feature.
+//!
+//! [`enter`] delegates the supervisor interrupts, `Breakpoint` and `UserEnvCall` to S-mode, and
+//! points `stvec` at the S-mode vector table in `.vector_s_table`:
+//! - index 0 is `CORE_LOCAL_S`, which saves a [`TrapFrame`] and dispatches like `CORE_LOCAL` through
+//!   the usual exception and core interrupt handlers, then returns with `sret`
+//! - the other indices are the `#[interrupt(supervisor)]` handlers, served by the S-mode context of
+//!   the PLIC (target 1)
+//!
+//! Handlers of traps taken in S-mode escalate to `SupervisorExceptionHandler`, the default logs the
+//! trap and halts. `ExceptionHandler` and the crash record use M-mode CSRs.
+//!
+//! Other exceptions, e.g. access faults, stay with the M-mode handlers. Delegate more by setting
+//! `medeleg` before `enter`, as long as their handlers do not use M-mode CSRs.
+//!
+//! ```ignore
+//! #[interrupt(supervisor, priority = 1, enable)]
+//! fn GPIO0_A() { ... }
+//!
+//! extern "C" fn kernel_main() -> ! { ... }
+//!
+//! unsafe { smode::enter(kernel_main, addr_of_mut!(KERNEL_STACK) as usize + 4096) }
+//! ```
+//!
+//! [`TrapFrame`]: crate::trap::TrapFrame
+
+use core::arch::asm;
+use core::ptr::{addr_of, addr_of_mut};
+
+use andes_riscv::riscv::register::{medeleg, mideleg, mie, stvec, stvec::TrapMode};
+
+use crate::pmp::{self, Permission, Range};
+
+/// Entries of the S-mode vector table, index 0 and the external interrupts 1 to 255
+const VECTORS: usize = 256;
+
+#[link_section = ".vector_s_table"]
+static mut VECTORED_INTERRUPTS_S: [usize; VECTORS] = [0; VECTORS];
+
+/// S-mode external interrupt handler, generated by `#[interrupt(supervisor)]`
+#[doc(hidden)]
+#[repr(C)]
+pub struct SupervisorInterrupt {
+    pub irq: u16,
+    pub handler: *const (),
+}
+
+unsafe impl Sync for SupervisorInterrupt {}
+
+/// Fill the S-mode vector table of this hart, unused interrupts go to `DefaultHandler`.
+unsafe fn setup_vectors() {
+    extern "C" {
+        fn CORE_LOCAL_S();
+        fn DefaultHandler();
+        static __supervisor_interrupts_start__: SupervisorInterrupt;
+        static __supervisor_interrupts_end__: SupervisorInterrupt;
+    }
+
+    let vectors = &mut *addr_of_mut!(VECTORED_INTERRUPTS_S);
+    vectors[0] = CORE_LOCAL_S as *const () as usize;
+    vectors[1..].fill(DefaultHandler as *const () as usize);
+
+    let start = addr_of!(__supervisor_interrupts_start__);
+    let end = addr_of!(__supervisor_interrupts_end__);
+    let handlers = core::slice::from_raw_parts(start, end.offset_from(start) as usize);
+    for handler in handlers {
+        vectors[handler.irq as usize] = handler.handler as usize;
+    }
+
+    // TrapMode is ignored, vectored mode is set in CSR_MMISC_CTL by `_setup_interrupts`
+    stvec::write(vectors.as_ptr() as usize, TrapMode::Direct);
+}
+
+/// Switch this hart to S-mode, running `entry` on the stack `stack_top`.
+///
+/// S-mode may access all memory, but the locked entries of `pmp-default`. The calling stack becomes
+/// the machine stack, used by the M-mode trap handlers. M-mode stack overflow detection of
+/// `stack-protection` is turned off, M-mode code only runs in trap handlers from here on, which run
+/// without it.
+///
+/// # Safety
+///
+/// `stack_top` must be the top of memory used by nothing else, aligned to 16 bytes.
+pub unsafe fn enter(entry: extern "C" fn() -> !, stack_top: usize) -> ! {
+    setup_vectors();
+
+    mideleg::set_ssoft();
+    mideleg::set_stimer();
+    mideleg::set_sext();
+    medeleg::set_breakpoint();
+    medeleg::set_user_env_call();
+    mie::set_sext();
+
+    // S-mode accesses matching no entry fail
+    pmp::set(11, Range::All, Permission::RWX, false);
+
+    // mhsp_ctl.OVF_EN, sp is about to leave the bounds
+    #[cfg(feature = "stack-protection")]
+    asm!("csrci 0x7c6, 1");
+
+    asm!(
+        // the machine stack for `CORE_LOCAL`, and 0 in S-mode for `CORE_LOCAL_S`
+        "csrw mscratch, sp",
+        "csrw sscratch, zero",
+        "csrw mepc, {entry}",
+        // MPP = S, interrupts enabled after `mret`
+        "csrc mstatus, {mpp}",
+        "csrs mstatus, {mpp_s}",
+        "csrs mstatus, {mpie}",
+        "mv sp, {stack}",
+        "mret",
+        entry = in(reg) entry,
+        mpp = in(reg) 0b11 << 11,
+        mpp_s = in(reg) 0b01 << 11,
+        mpie = in(reg) 1 << 7,
+        stack = in(reg) stack_top,
+        options(noreturn),
+    );
+}
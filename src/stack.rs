@@ -0,0 +1,30 @@
+//! Stack usage of the current hart, measured from the pattern painted by `_start`.
+//!
+//! Only the stack below `sp` at `_start` is painted, so usage before `__pre_init` is not counted.
+
+use crate::hart_stack;
+
+/// Pattern written to the stack by `_start`, keep in sync with the assembly
+pub const PAINT: u32 = 0xc5c5_c5c5;
+
+/// Bytes at the bottom of the stack never written since reset
+pub fn unused() -> usize {
+    let (top, bottom) = hart_stack();
+    let mut addr = bottom;
+    while addr < top && unsafe { core::ptr::read_volatile(addr as *const u32) } == PAINT {
+        addr += 4;
+    }
+    addr - bottom
+}
+
+/// Maximum stack usage in bytes since reset
+pub fn high_water_mark() -> usize {
+    let (top, bottom) = hart_stack();
+    top - bottom - unused()
+}
+
+/// Size of the stack of the current hart in bytes
+pub fn size() -> usize {
+    let (top, bottom) = hart_stack();
+    top - bottom
+}
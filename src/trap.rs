@@ -1,11 +1,18 @@
 use core::arch::global_asm;
 
-use andes_riscv::riscv::register::mcause;
+use andes_riscv::riscv::register::mcause::{Exception, Interrupt, Trap};
+use andes_riscv::riscv::register::mstatus::MPP;
 
 /// Registers saved in trap handler
+///
+/// Handlers may modify the saved registers, they are restored on return.
+/// `sp`, `mepc` and `mstatus` are written back too, so an exception handler can skip the faulting
+/// instruction. Traps from U-mode are saved on the machine stack, see [`crate::umode`].
+///
+/// Traps taken in S-mode (see the `smode` feature) save `sepc`, `scause`, `stval` and `sstatus` in the
+/// `m*` fields, with `sstatus.SPP` copied to `mstatus.MPP`.
 #[allow(missing_docs)]
 #[repr(C)]
-#[derive(Debug)]
 pub struct TrapFrame {
     pub ra: usize,
     pub t0: usize,
@@ -23,11 +30,279 @@ pub struct TrapFrame {
     pub a5: usize,
     pub a6: usize,
     pub a7: usize,
+    pub s0: usize,
+    pub s1: usize,
+    pub s2: usize,
+    pub s3: usize,
+    pub s4: usize,
+    pub s5: usize,
+    pub s6: usize,
+    pub s7: usize,
+    pub s8: usize,
+    pub s9: usize,
+    pub s10: usize,
+    pub s11: usize,
+    /// `sp` before the trap was taken
+    pub sp: usize,
+    pub mepc: usize,
+    pub mcause: usize,
+    pub mtval: usize,
+    pub mstatus: usize,
 }
 
+// keep in sync with the offsets used by `CORE_LOCAL`
+const _: () = assert!(core::mem::size_of::<TrapFrame>() == 33 * 4);
+
+impl TrapFrame {
+    /// Decoded `mcause`
+    pub fn cause(&self) -> Trap {
+        let code = self.mcause & !(1 << (usize::BITS - 1));
+        if self.mcause >> (usize::BITS - 1) != 0 {
+            Trap::Interrupt(Interrupt::from(code))
+        } else {
+            Trap::Exception(Exception::from(code))
+        }
+    }
+
+    /// Privilege mode of the interrupted code, `mstatus.MPP`
+    pub fn mode(&self) -> MPP {
+        match (self.mstatus >> 11) & 0b11 {
+            0b00 => MPP::User,
+            0b01 => MPP::Supervisor,
+            _ => MPP::Machine,
+        }
+    }
+
+    /// Return addresses of the interrupted code, walked from `s0`, see [`crate::backtrace`]
+    #[cfg(feature = "backtrace")]
+    pub fn backtrace(&self) -> crate::backtrace::Backtrace {
+        crate::backtrace::Backtrace::from_fp(self.s0)
+    }
+
+    fn t(&self) -> [usize; 7] {
+        [
+            self.t0, self.t1, self.t2, self.t3, self.t4, self.t5, self.t6,
+        ]
+    }
+
+    fn a(&self) -> [usize; 8] {
+        [
+            self.a0, self.a1, self.a2, self.a3, self.a4, self.a5, self.a6, self.a7,
+        ]
+    }
+
+    fn s(&self) -> [usize; 12] {
+        [
+            self.s0, self.s1, self.s2, self.s3, self.s4, self.s5, self.s6, self.s7, self.s8,
+            self.s9, self.s10, self.s11,
+        ]
+    }
+}
+
+impl core::fmt::Debug for TrapFrame {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("TrapFrame")
+            .field("cause", &self.cause())
+            .field("mepc", &format_args!("{:#010x}", self.mepc))
+            .field("mtval", &format_args!("{:#010x}", self.mtval))
+            .field("mstatus", &format_args!("{:#010x}", self.mstatus))
+            .field("sp", &format_args!("{:#010x}", self.sp))
+            .field("ra", &format_args!("{:#010x}", self.ra))
+            .field("t", &self.t())
+            .field("a", &self.a())
+            .field("s", &self.s())
+            .finish()
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for TrapFrame {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(
+            f,
+            "TrapFrame {{ cause: {}, mepc: {=usize:#010x}, mtval: {=usize:#010x}, mstatus: {=usize:#010x}, sp: {=usize:#010x}, ra: {=usize:#010x}, t: {:#x}, a: {:#x}, s: {:#x} }}",
+            defmt::Debug2Format(&self.cause()),
+            self.mepc,
+            self.mtval,
+            self.mstatus,
+            self.sp,
+            self.ra,
+            self.t(),
+            self.a(),
+            self.s(),
+        )
+    }
+}
+
+/// What the runtime does after an exception handler returns
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ExceptionAction {
+    /// Return to `mepc`, e.g. after the handler fixed the cause or changed `mepc`
+    Resume,
+    /// Advance `mepc` past the faulting instruction, then return
+    SkipInstruction,
+    /// Call `ExceptionHandler`
+    Escalate,
+}
+
+/// Default of the specific exception handlers, passes the exception to `ExceptionHandler`.
+#[no_mangle]
+#[allow(unused_variables, non_snake_case)]
+pub extern "C" fn DefaultExceptionSlotHandler(trap_frame: &mut TrapFrame) -> ExceptionAction {
+    ExceptionAction::Escalate
+}
+
+/// Default `StackOverflow` handler, reports the overflowing `sp` and `mepc` and escalates.
+///
+/// `trap_frame.sp` is the stack pointer before the faulting instruction, which was not executed.
+#[no_mangle]
+#[allow(unused_variables, non_snake_case)]
+pub extern "C" fn DefaultStackOverflowHandler(trap_frame: &mut TrapFrame) -> ExceptionAction {
+    #[cfg(feature = "defmt")]
+    defmt::error!(
+        "stack overflow, sp: {=usize:#010x}, mepc: {=usize:#010x}",
+        trap_frame.sp,
+        trap_frame.mepc
+    );
+    ExceptionAction::Escalate
+}
+
+/// Entry of the `#[syscall(N)]` table
+#[doc(hidden)]
+#[repr(C)]
+pub struct Syscall {
+    pub number: usize,
+    pub handler: unsafe extern "C" fn(&mut TrapFrame) -> usize,
+}
+
+/// Default `UserEnvCall`, `SupervisorEnvCall` and `MachineEnvCall` handler, calls the `#[syscall(N)]`
+/// handler of the number in `a7` and returns its result in `a0`.
+///
+/// Unknown numbers return -1 in `a0`, the caller is not trusted to only use existing ones.
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "C" fn DefaultEnvCallHandler(trap_frame: &mut TrapFrame) -> ExceptionAction {
+    extern "C" {
+        static __syscalls_start__: Syscall;
+        static __syscalls_end__: Syscall;
+    }
+
+    let syscalls = unsafe {
+        let start = core::ptr::addr_of!(__syscalls_start__);
+        let end = core::ptr::addr_of!(__syscalls_end__);
+        core::slice::from_raw_parts(start, end.offset_from(start) as usize)
+    };
+
+    trap_frame.a0 = match syscalls.iter().find(|s| s.number == trap_frame.a7) {
+        Some(syscall) => unsafe { (syscall.handler)(trap_frame) },
+        None => usize::MAX,
+    };
+    // `ecall` has no compressed form
+    trap_frame.mepc += 4;
+    ExceptionAction::Resume
+}
+
+/// Default `Breakpoint` handler, fails semihosting requests nobody served with -1 in `a0` and
+/// escalates other breakpoints.
+///
+/// With a debugger or an emulator serving semihosting, requests do not trap.
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "C" fn DefaultBreakpointHandler(trap_frame: &mut TrapFrame) -> ExceptionAction {
+    if unsafe { is_semihosting_request(trap_frame.mepc) } {
+        trap_frame.a0 = usize::MAX;
+        ExceptionAction::SkipInstruction
+    } else {
+        ExceptionAction::Escalate
+    }
+}
+
+/// Whether the `ebreak` at `pc` is surrounded by `slli x0, x0, 0x1f` and `srai x0, x0, 7`.
+unsafe fn is_semihosting_request(pc: usize) -> bool {
+    const SLLI: u32 = 0x01f0_1013;
+    const EBREAK: u32 = 0x0010_0073;
+    const SRAI: u32 = 0x4070_5013;
+
+    // instructions are only 2 byte aligned with compressed instructions
+    let read = |addr: usize| {
+        let p = addr as *const u16;
+        p.read_volatile() as u32 | (p.add(1).read_volatile() as u32) << 16
+    };
+    // an `ebreak` at the very start of the address space has no `slli` before it
+    let Some(prev) = pc.checked_sub(4) else {
+        return false;
+    };
+    // a `c.ebreak` ends here, it may be the last instruction of the memory
+    (pc as *const u16).read_volatile() == EBREAK as u16
+        && read(pc) == EBREAK
+        && read(prev) == SLLI
+        && read(pc + 4) == SRAI
+}
+
+/// Default `LoadMisaligned` handler, emulates the load with the `emulate-misaligned` feature,
+/// escalates otherwise.
+#[no_mangle]
+#[allow(unused_variables, non_snake_case)]
+pub extern "C" fn DefaultLoadMisalignedHandler(trap_frame: &mut TrapFrame) -> ExceptionAction {
+    #[cfg(feature = "emulate-misaligned")]
+    return unsafe { misaligned::emulate(trap_frame) };
+    #[cfg(not(feature = "emulate-misaligned"))]
+    ExceptionAction::Escalate
+}
+
+/// Default `StoreMisaligned` handler, emulates the store with the `emulate-misaligned` feature,
+/// escalates otherwise.
 #[no_mangle]
 #[allow(unused_variables, non_snake_case)]
-pub fn DefaultExceptionHandler(trap_frame: &TrapFrame) -> ! {
+pub extern "C" fn DefaultStoreMisalignedHandler(trap_frame: &mut TrapFrame) -> ExceptionAction {
+    #[cfg(feature = "emulate-misaligned")]
+    return unsafe { misaligned::emulate(trap_frame) };
+    #[cfg(not(feature = "emulate-misaligned"))]
+    ExceptionAction::Escalate
+}
+
+#[cfg(feature = "emulate-misaligned")]
+mod misaligned;
+
+#[no_mangle]
+#[allow(unused_variables, non_snake_case)]
+pub fn DefaultExceptionHandler(trap_frame: &mut TrapFrame) -> ! {
+    #[cfg(all(feature = "backtrace", feature = "defmt"))]
+    {
+        defmt::error!("{}", trap_frame);
+        for ra in trap_frame.backtrace() {
+            defmt::error!("  at {=usize:#010x}", ra);
+        }
+    }
+    #[cfg(feature = "crash-report")]
+    unsafe {
+        crate::crash::record(trap_frame);
+        crate::crash::reset();
+    }
+    #[allow(unreachable_code)]
+    loop {
+        // Prevent this from turning into a UDF instruction
+        // see rust-lang/rust#28728 for details
+        continue;
+    }
+}
+
+/// Default `SupervisorExceptionHandler`, for exceptions taken in S-mode that their handlers escalate.
+///
+/// Logs like `DefaultExceptionHandler`, but records no crash: that needs M-mode CSRs.
+#[cfg(feature = "smode")]
+#[no_mangle]
+#[allow(unused_variables, non_snake_case)]
+pub fn DefaultSupervisorExceptionHandler(trap_frame: &mut TrapFrame) -> ! {
+    #[cfg(all(feature = "backtrace", feature = "defmt"))]
+    {
+        defmt::error!("{}", trap_frame);
+        for ra in trap_frame.backtrace() {
+            defmt::error!("  at {=usize:#010x}", ra);
+        }
+    }
     loop {
         // Prevent this from turning into a UDF instruction
         // see rust-lang/rust#28728 for details
@@ -47,26 +322,27 @@ pub fn DefaultInterruptHandler() {
 }
 
 extern "C" {
-    fn InstructionMisaligned(trap_frame: &TrapFrame);
-    fn InstructionFault(trap_frame: &TrapFrame);
-    fn IllegalInstruction(trap_frame: &TrapFrame);
-    fn Breakpoint(trap_frame: &TrapFrame);
-    fn LoadMisaligned(trap_frame: &TrapFrame);
-    fn LoadFault(trap_frame: &TrapFrame);
-    fn StoreMisaligned(trap_frame: &TrapFrame);
-    fn StoreFault(trap_frame: &TrapFrame);
-    fn UserEnvCall(trap_frame: &TrapFrame);
-    fn SupervisorEnvCall(trap_frame: &TrapFrame);
-    fn MachineEnvCall(trap_frame: &TrapFrame);
-    fn InstructionPageFault(trap_frame: &TrapFrame);
-    fn LoadPageFault(trap_frame: &TrapFrame);
-    fn StorePageFault(trap_frame: &TrapFrame);
+    fn InstructionMisaligned(trap_frame: &mut TrapFrame) -> ExceptionAction;
+    fn InstructionFault(trap_frame: &mut TrapFrame) -> ExceptionAction;
+    fn IllegalInstruction(trap_frame: &mut TrapFrame) -> ExceptionAction;
+    fn Breakpoint(trap_frame: &mut TrapFrame) -> ExceptionAction;
+    fn LoadMisaligned(trap_frame: &mut TrapFrame) -> ExceptionAction;
+    fn LoadFault(trap_frame: &mut TrapFrame) -> ExceptionAction;
+    fn StoreMisaligned(trap_frame: &mut TrapFrame) -> ExceptionAction;
+    fn StoreFault(trap_frame: &mut TrapFrame) -> ExceptionAction;
+    fn UserEnvCall(trap_frame: &mut TrapFrame) -> ExceptionAction;
+    fn SupervisorEnvCall(trap_frame: &mut TrapFrame) -> ExceptionAction;
+    fn MachineEnvCall(trap_frame: &mut TrapFrame) -> ExceptionAction;
+    fn InstructionPageFault(trap_frame: &mut TrapFrame) -> ExceptionAction;
+    fn LoadPageFault(trap_frame: &mut TrapFrame) -> ExceptionAction;
+    fn StorePageFault(trap_frame: &mut TrapFrame) -> ExceptionAction;
+    fn StackOverflow(trap_frame: &mut TrapFrame) -> ExceptionAction;
 }
 
 // UM: 4.1.1
 #[doc(hidden)]
 #[no_mangle]
-pub static __EXCEPTIONS: [Option<unsafe extern "C" fn(&TrapFrame)>; 16] = [
+pub static __EXCEPTIONS: [Option<unsafe extern "C" fn(&mut TrapFrame) -> ExceptionAction>; 33] = [
     // Instruction Address misaligned
     Some(InstructionMisaligned),
     // Instruction access fault
@@ -92,6 +368,24 @@ pub static __EXCEPTIONS: [Option<unsafe extern "C" fn(&TrapFrame)>; 16] = [
     None,
     // Store/AMO page fault
     Some(StorePageFault),
+    None,
+    None,
+    None,
+    None,
+    None,
+    None,
+    None,
+    None,
+    None,
+    None,
+    None,
+    None,
+    None,
+    None,
+    None,
+    None,
+    // Andes: stack overflow, sp went below `msp_bound`
+    Some(StackOverflow),
 ];
 
 extern "C" {
@@ -130,37 +424,64 @@ pub static __INTERRUPTS: [Option<unsafe extern "C" fn()>; 14] = [
 #[no_mangle]
 #[allow(non_snake_case)]
 #[link_section = ".isr_vector"]
-unsafe extern "C" fn _start_rust_CORE_LOCAL(trap_frame: *const TrapFrame) {
+unsafe extern "C" fn _start_rust_CORE_LOCAL(trap_frame: *mut TrapFrame) {
+    dispatch(&mut *trap_frame, false)
+}
+
+// S-mode vector index 0, see `crate::smode`
+#[cfg(feature = "smode")]
+#[no_mangle]
+#[allow(non_snake_case)]
+#[link_section = ".isr_s_vector"]
+unsafe extern "C" fn _start_rust_CORE_LOCAL_S(trap_frame: *mut TrapFrame) {
+    dispatch(&mut *trap_frame, true)
+}
+
+/// `supervisor` for traps taken in S-mode, they escalate to `SupervisorExceptionHandler` as
+/// `ExceptionHandler` may use M-mode CSRs.
+#[inline(always)]
+#[allow(unused_variables)]
+unsafe fn dispatch(trap_frame: &mut TrapFrame, supervisor: bool) {
     extern "C" {
-        fn ExceptionHandler(trap_frame: &TrapFrame);
+        fn ExceptionHandler(trap_frame: &mut TrapFrame);
+        #[cfg(feature = "smode")]
+        fn SupervisorExceptionHandler(trap_frame: &mut TrapFrame);
         fn DefaultHandler();
     }
 
-    let cause = mcause::read();
-    let code = cause.code();
+    let code = trap_frame.mcause & !(1 << (usize::BITS - 1));
 
-    if cause.is_exception() {
+    if matches!(trap_frame.cause(), Trap::Exception(_)) {
         // Ref: HPM6700_6400_Errata_V2_0.pdf "E00001：RISC-V 处理器指令和数据本地存储器使用限制"
         #[cfg(feature = "hpm67-fix")]
         if code == 2 {
             // Illegal instruction
-            if andes_riscv::riscv::register::mtval::read() == 0x0 {
+            if trap_frame.mtval == 0x0 {
                 return;
             }
         }
 
-        let trap_frame = &*trap_frame;
-        if code < __EXCEPTIONS.len() {
-            let h = &__EXCEPTIONS[code];
-            if let Some(handler) = h {
-                handler(trap_frame);
-            } else {
-                ExceptionHandler(trap_frame);
+        // an access of the misaligned emulation faulted, it reports the fault itself
+        #[cfg(feature = "emulate-misaligned")]
+        if !supervisor && (code == 5 || code == 7) && misaligned::catch_fault(trap_frame) {
+            return;
+        }
+
+        let action = match __EXCEPTIONS.get(code) {
+            Some(Some(handler)) => handler(trap_frame),
+            _ => ExceptionAction::Escalate,
+        };
+        match action {
+            ExceptionAction::Resume => (),
+            ExceptionAction::SkipInstruction => {
+                // 16-bit compressed instructions do not have 0b11 in the lowest bits
+                let insn = core::ptr::read_volatile(trap_frame.mepc as *const u16);
+                trap_frame.mepc += if insn & 0b11 == 0b11 { 4 } else { 2 };
             }
-        } else {
-            ExceptionHandler(trap_frame);
+            #[cfg(feature = "smode")]
+            ExceptionAction::Escalate if supervisor => SupervisorExceptionHandler(trap_frame),
+            ExceptionAction::Escalate => ExceptionHandler(trap_frame),
         }
-        ExceptionHandler(trap_frame)
     } else if code < __INTERRUPTS.len() {
         let h = &__INTERRUPTS[code];
         if let Some(handler) = h {
@@ -173,13 +494,46 @@ unsafe extern "C" fn _start_rust_CORE_LOCAL(trap_frame: *const TrapFrame) {
     }
 }
 
-global_asm!(
+cfg_global_asm!(
     r#"
     .section .isr_vector, "ax"
     .global CORE_LOCAL
 CORE_LOCAL:
-    // save registers
-    addi sp, sp, -(16 * 4)
+    // mscratch is the machine stack pointer while in U- or S-mode, 0 in M-mode
+    csrrw t0, mscratch, t0
+    bnez t0, 1f
+
+    // from M-mode, t0 is kept in mscratch
+    "#,
+    // turn off the stack overflow detection while the frame is saved, sp may already be at
+    // `msp_bound`. mhsp_ctl = 0x7c6, the old value is kept in the spare word after `TrapFrame`.
+    #[cfg(feature = "stack-protection")]
+    "csrrci t0, 0x7c6, 1
+    addi sp, sp, -(36 * 4)
+    sw t0, 132(sp)
+    ",
+    #[cfg(not(feature = "stack-protection"))]
+    "addi sp, sp, -(36 * 4)",
+    r#"
+    addi t0, sp, 36 * 4
+    sw t0, 112(sp)
+    j 2f
+
+1:
+    // from U- or S-mode, the frame goes on the machine stack
+    addi t0, t0, -(36 * 4)
+    sw sp, 112(t0)
+    mv sp, t0
+    "#,
+    #[cfg(feature = "stack-protection")]
+    "csrrci t0, 0x7c6, 1
+    sw t0, 132(sp)
+    ",
+    r#"
+2:
+    csrrw t0, mscratch, zero
+
+    // save registers, the layout is `TrapFrame`
     sw ra, 0(sp)
     sw t0, 4(sp)
     sw t1, 8(sp)
@@ -196,11 +550,50 @@ CORE_LOCAL:
     sw a5, 52(sp)
     sw a6, 56(sp)
     sw a7, 60(sp)
+    sw s0, 64(sp)
+    sw s1, 68(sp)
+    sw s2, 72(sp)
+    sw s3, 76(sp)
+    sw s4, 80(sp)
+    sw s5, 84(sp)
+    sw s6, 88(sp)
+    sw s7, 92(sp)
+    sw s8, 96(sp)
+    sw s9, 100(sp)
+    sw s10, 104(sp)
+    sw s11, 108(sp)
+    csrr t0, mepc
+    sw t0, 116(sp)
+    csrr t0, mcause
+    sw t0, 120(sp)
+    csrr t0, mtval
+    sw t0, 124(sp)
+    csrr t0, mstatus
+    sw t0, 128(sp)
 
     add a0, sp, zero
     jal ra, _start_rust_CORE_LOCAL
 
-    // restore registers
+    // restore registers, sp, mepc and mstatus may have been changed by the handler
+    lw t0, 116(sp)
+    csrw mepc, t0
+    lw t0, 128(sp)
+    csrw mstatus, t0
+    "#,
+    #[cfg(feature = "stack-protection")]
+    "lw t0, 132(sp)
+    csrw 0x7c6, t0
+    ",
+    r#"
+    // back to U- or S-mode (MPP != 3), the next trap uses the machine stack above this frame
+    lw t0, 128(sp)
+    srli t0, t0, 11
+    andi t0, t0, 0b11
+    addi t0, t0, -0b11
+    beqz t0, 3f
+    addi t0, sp, 36 * 4
+    csrw mscratch, t0
+3:
     lw ra, 0(sp)
     lw t0, 4(sp)
     lw t1, 8(sp)
@@ -217,8 +610,178 @@ CORE_LOCAL:
     lw a5, 52(sp)
     lw a6, 56(sp)
     lw a7, 60(sp)
-    addi sp, sp, 16 * 4
+    lw s0, 64(sp)
+    lw s1, 68(sp)
+    lw s2, 72(sp)
+    lw s3, 76(sp)
+    lw s4, 80(sp)
+    lw s5, 84(sp)
+    lw s6, 88(sp)
+    lw s7, 92(sp)
+    lw s8, 96(sp)
+    lw s9, 100(sp)
+    lw s10, 104(sp)
+    lw s11, 108(sp)
+    lw sp, 112(sp)
 
     mret
     "#,
 );
+
+// External interrupts taken in U- or S-mode, called by the stubs of `#[interrupt]` with the 32 byte
+// frame allocated on the machine stack, `ra` saved at 0(sp) and the interrupted `sp` in mscratch.
+// The handler runs on the machine stack, its `mret` returns to `__hpm_riscv_rt_external_return`
+// in M-mode with MIE clear.
+global_asm!(
+    r#"
+    .section .isr_vector, "ax"
+    .global __hpm_riscv_rt_external_enter
+__hpm_riscv_rt_external_enter:
+    sw t0, 4(sp)
+    csrrw t0, mscratch, zero
+    sw t0, 8(sp)
+    csrr t0, mepc
+    sw t0, 12(sp)
+    csrr t0, mstatus
+    sw t0, 16(sp)
+
+    la t0, __hpm_riscv_rt_external_return
+    csrw mepc, t0
+    // MPP = M, MPIE = 0
+    li t0, 0b11 << 11
+    csrs mstatus, t0
+    li t0, 1 << 7
+    csrc mstatus, t0
+    lw t0, 4(sp)
+    ret
+
+__hpm_riscv_rt_external_return:
+    lw t0, 12(sp)
+    csrw mepc, t0
+    lw t0, 16(sp)
+    csrw mstatus, t0
+    // the machine stack for the next trap
+    addi t0, sp, 32
+    csrw mscratch, t0
+    lw ra, 0(sp)
+    lw t0, 4(sp)
+    lw sp, 8(sp)
+    mret
+    "#,
+);
+
+#[cfg(feature = "smode")]
+global_asm!(
+    r#"
+    .section .isr_s_vector, "ax"
+    .global CORE_LOCAL_S
+CORE_LOCAL_S:
+    // sscratch is the supervisor stack pointer while in U-mode, 0 in S-mode
+    csrrw t0, sscratch, t0
+    bnez t0, 1f
+
+    // from S-mode, t0 is kept in sscratch
+    addi sp, sp, -(36 * 4)
+    addi t0, sp, 36 * 4
+    sw t0, 112(sp)
+    j 2f
+
+1:
+    // from U-mode, the frame goes on the supervisor stack
+    addi t0, t0, -(36 * 4)
+    sw sp, 112(t0)
+    mv sp, t0
+
+2:
+    csrrw t0, sscratch, zero
+
+    // save registers, the layout is `TrapFrame`
+    sw ra, 0(sp)
+    sw t0, 4(sp)
+    sw t1, 8(sp)
+    sw t2, 12(sp)
+    sw t3, 16(sp)
+    sw t4, 20(sp)
+    sw t5, 24(sp)
+    sw t6, 28(sp)
+    sw a0, 32(sp)
+    sw a1, 36(sp)
+    sw a2, 40(sp)
+    sw a3, 44(sp)
+    sw a4, 48(sp)
+    sw a5, 52(sp)
+    sw a6, 56(sp)
+    sw a7, 60(sp)
+    sw s0, 64(sp)
+    sw s1, 68(sp)
+    sw s2, 72(sp)
+    sw s3, 76(sp)
+    sw s4, 80(sp)
+    sw s5, 84(sp)
+    sw s6, 88(sp)
+    sw s7, 92(sp)
+    sw s8, 96(sp)
+    sw s9, 100(sp)
+    sw s10, 104(sp)
+    sw s11, 108(sp)
+    csrr t0, sepc
+    sw t0, 116(sp)
+    csrr t0, scause
+    sw t0, 120(sp)
+    csrr t0, stval
+    sw t0, 124(sp)
+    // SPP (bit 8) as MPP (bits 12:11), those bits of sstatus read as 0 and ignore writes
+    csrr t0, sstatus
+    andi t1, t0, 0x100
+    slli t1, t1, 3
+    or t0, t0, t1
+    sw t0, 128(sp)
+
+    add a0, sp, zero
+    jal ra, _start_rust_CORE_LOCAL_S
+
+    // restore registers, sp, sepc and sstatus may have been changed by the handler
+    lw t0, 116(sp)
+    csrw sepc, t0
+    lw t0, 128(sp)
+    csrw sstatus, t0
+
+    // back to U-mode (SPP = 0), the next trap uses the supervisor stack above this frame
+    andi t0, t0, 0x100
+    bnez t0, 3f
+    addi t0, sp, 36 * 4
+    csrw sscratch, t0
+3:
+    lw ra, 0(sp)
+    lw t0, 4(sp)
+    lw t1, 8(sp)
+    lw t2, 12(sp)
+    lw t3, 16(sp)
+    lw t4, 20(sp)
+    lw t5, 24(sp)
+    lw t6, 28(sp)
+    lw a0, 32(sp)
+    lw a1, 36(sp)
+    lw a2, 40(sp)
+    lw a3, 44(sp)
+    lw a4, 48(sp)
+    lw a5, 52(sp)
+    lw a6, 56(sp)
+    lw a7, 60(sp)
+    lw s0, 64(sp)
+    lw s1, 68(sp)
+    lw s2, 72(sp)
+    lw s3, 76(sp)
+    lw s4, 80(sp)
+    lw s5, 84(sp)
+    lw s6, 88(sp)
+    lw s7, 92(sp)
+    lw s8, 96(sp)
+    lw s9, 100(sp)
+    lw s10, 104(sp)
+    lw s11, 108(sp)
+    lw sp, 112(sp)
+
+    sret
+    "#,
+);
@@ -0,0 +1,178 @@
+//! Bytewise emulation of misaligned integer loads and stores, RV32I and RV32C.
+//!
+//! Floating point loads and stores, and accesses through `gp`/`tp` escalate: those registers are not
+//! saved in the `TrapFrame`. Accesses are done with the privilege of the interrupted code, so those
+//! of U-mode code are checked by the PMP. A faulting access goes to `LoadFault`/`StoreFault` as a
+//! fault of the interrupted code.
+
+use core::arch::asm;
+
+use super::{ExceptionAction, TrapFrame, MPP};
+
+mod decode;
+
+use decode::{decode, extend, Op};
+
+/// `mstatus.MPRV`, loads and stores with the privilege of `mstatus.MPP`
+const MPRV: usize = 1 << 17;
+
+/// `x0`..`x31` of the trap frame, `None` for `gp` and `tp` which are not saved
+fn reg(trap_frame: &mut TrapFrame, x: u8) -> Option<&mut usize> {
+    let f = trap_frame;
+    Some(match x {
+        1 => &mut f.ra,
+        2 => &mut f.sp,
+        5 => &mut f.t0,
+        6 => &mut f.t1,
+        7 => &mut f.t2,
+        8 => &mut f.s0,
+        9 => &mut f.s1,
+        10 => &mut f.a0,
+        11 => &mut f.a1,
+        12 => &mut f.a2,
+        13 => &mut f.a3,
+        14 => &mut f.a4,
+        15 => &mut f.a5,
+        16 => &mut f.a6,
+        17 => &mut f.a7,
+        18 => &mut f.s2,
+        19 => &mut f.s3,
+        20 => &mut f.s4,
+        21 => &mut f.s5,
+        22 => &mut f.s6,
+        23 => &mut f.s7,
+        24 => &mut f.s8,
+        25 => &mut f.s9,
+        26 => &mut f.s10,
+        27 => &mut f.s11,
+        28 => &mut f.t3,
+        29 => &mut f.t4,
+        30 => &mut f.t5,
+        31 => &mut f.t6,
+        _ => return None,
+    })
+}
+
+fn read_reg(trap_frame: &mut TrapFrame, x: u8) -> Option<usize> {
+    if x == 0 {
+        Some(0)
+    } else {
+        reg(trap_frame, x).map(|r| *r)
+    }
+}
+
+/// Resume [`emulate`] after one of its accesses faulted, `true` if the fault was one of them.
+///
+/// Only `emulate` runs M-mode code with `mstatus.MPRV` set. Its `lbu`/`sb` are assembled with
+/// `norvc`, the instruction 4 bytes after them checks `a2`.
+pub(super) fn catch_fault(trap_frame: &mut TrapFrame) -> bool {
+    if trap_frame.mode() != MPP::Machine || trap_frame.mstatus & MPRV == 0 {
+        return false;
+    }
+    trap_frame.a2 = 1;
+    trap_frame.mepc += 4;
+    true
+}
+
+pub(super) unsafe fn emulate(trap_frame: &mut TrapFrame) -> ExceptionAction {
+    // `mepc` is only 2 byte aligned with compressed instructions
+    let pc = trap_frame.mepc as *const u16;
+    let low = pc.read_volatile() as u32;
+    let insn = if low & 0b11 == 0b11 {
+        low | (pc.add(1).read_volatile() as u32) << 16
+    } else {
+        low
+    };
+
+    let Some(access) = decode(insn) else {
+        return ExceptionAction::Escalate;
+    };
+    let Some(base) = read_reg(trap_frame, access.rs1) else {
+        return ExceptionAction::Escalate;
+    };
+    let addr = base.wrapping_add(access.offset as usize);
+
+    // `MPRV` is set for the whole loop, nothing else may touch memory in between. `a0` is the
+    // address of the next byte, `a2` is set by `catch_fault` when an access faulted.
+    let (fault_addr, fault): (usize, usize);
+    match access.op {
+        Op::Load { rd, signed } => {
+            let value: usize;
+            asm!(
+                ".option push",
+                ".option norvc",
+                "csrs mstatus, {mprv}",
+                "1:",
+                "lbu {byte}, 0(a0)",
+                "bnez a2, 2f",
+                "sll {byte}, {byte}, {shift}",
+                "or {value}, {value}, {byte}",
+                "addi {shift}, {shift}, 8",
+                "addi a0, a0, 1",
+                "addi a1, a1, -1",
+                "bnez a1, 1b",
+                "2:",
+                "csrc mstatus, {mprv}",
+                ".option pop",
+                mprv = in(reg) MPRV,
+                byte = out(reg) _,
+                shift = inout(reg) 0usize => _,
+                value = inout(reg) 0usize => value,
+                inout("a0") addr => fault_addr,
+                inout("a1") access.width => _,
+                inout("a2") 0usize => fault,
+                options(nostack),
+            );
+            if fault == 0 && rd != 0 {
+                let Some(rd) = reg(trap_frame, rd) else {
+                    return ExceptionAction::Escalate;
+                };
+                *rd = extend(value, access.width, signed);
+            }
+        }
+        Op::Store { rs2 } => {
+            let Some(value) = read_reg(trap_frame, rs2) else {
+                return ExceptionAction::Escalate;
+            };
+            asm!(
+                ".option push",
+                ".option norvc",
+                "csrs mstatus, {mprv}",
+                "1:",
+                "sb {value}, 0(a0)",
+                "bnez a2, 2f",
+                "srli {value}, {value}, 8",
+                "addi a0, a0, 1",
+                "addi a1, a1, -1",
+                "bnez a1, 1b",
+                "2:",
+                "csrc mstatus, {mprv}",
+                ".option pop",
+                mprv = in(reg) MPRV,
+                value = inout(reg) value => _,
+                inout("a0") addr => fault_addr,
+                inout("a1") access.width => _,
+                inout("a2") 0usize => fault,
+                options(nostack),
+            );
+        }
+    }
+
+    if fault != 0 {
+        // report the fault as one of the emulated instruction, mcause 5 and 7 are Load/StoreFault
+        trap_frame.mtval = fault_addr;
+        return match access.op {
+            Op::Load { .. } => {
+                trap_frame.mcause = 5;
+                super::LoadFault(trap_frame)
+            }
+            Op::Store { .. } => {
+                trap_frame.mcause = 7;
+                super::StoreFault(trap_frame)
+            }
+        };
+    }
+
+    trap_frame.mepc += access.len;
+    ExceptionAction::Resume
+}
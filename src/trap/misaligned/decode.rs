@@ -0,0 +1,228 @@
+//! Decoding of the integer loads and stores, RV32I and RV32C.
+//!
+//! Target independent, its tests run on the host in `host-tests`.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Load { rd: u8, signed: bool },
+    Store { rs2: u8 },
+}
+
+/// A decoded load or store
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Access {
+    pub op: Op,
+    pub rs1: u8,
+    pub offset: i32,
+    /// Bytes accessed
+    pub width: usize,
+    /// Length of the instruction
+    pub len: usize,
+}
+
+/// Decode a load or store, `insn` is 16 bits for compressed instructions.
+pub fn decode(insn: u32) -> Option<Access> {
+    if insn & 0b11 == 0b11 {
+        decode_32(insn)
+    } else {
+        decode_16(insn as u16)
+    }
+}
+
+fn decode_32(insn: u32) -> Option<Access> {
+    let funct3 = (insn >> 12) & 0b111;
+    let rs1 = ((insn >> 15) & 0x1f) as u8;
+    let width = match funct3 & 0b11 {
+        0 => 1,
+        1 => 2,
+        2 => 4,
+        _ => return None,
+    };
+    let (op, offset) = match insn & 0x7f {
+        // LB, LH, LW, LBU, LHU
+        0b000_0011 if funct3 != 0b110 => (
+            Op::Load {
+                rd: ((insn >> 7) & 0x1f) as u8,
+                signed: funct3 & 0b100 == 0,
+            },
+            (insn as i32) >> 20,
+        ),
+        // SB, SH, SW
+        0b010_0011 if funct3 & 0b100 == 0 => (
+            Op::Store {
+                rs2: ((insn >> 20) & 0x1f) as u8,
+            },
+            (((insn & 0xfe00_0000) as i32) >> 20) | ((insn >> 7) & 0x1f) as i32,
+        ),
+        _ => return None,
+    };
+    Some(Access {
+        op,
+        rs1,
+        offset,
+        width,
+        len: 4,
+    })
+}
+
+fn decode_16(insn: u16) -> Option<Access> {
+    let insn = insn as u32;
+    let funct3 = insn >> 13;
+    // x8..x15 of the 3 bit register fields
+    let rd_rs2_c = (((insn >> 2) & 0b111) + 8) as u8;
+    let rs1_c = (((insn >> 7) & 0b111) + 8) as u8;
+    // uimm[5:3|2|6] of C.LW/C.SW
+    let uimm = ((insn >> 7) & 0b11_1000) | ((insn >> 4) & 0b100) | ((insn << 1) & 0b100_0000);
+    let rd = ((insn >> 7) & 0x1f) as u8;
+
+    let (op, rs1, offset) = match (insn & 0b11, funct3) {
+        // C.LW
+        (0b00, 0b010) => (
+            Op::Load {
+                rd: rd_rs2_c,
+                signed: true,
+            },
+            rs1_c,
+            uimm,
+        ),
+        // C.SW
+        (0b00, 0b110) => (Op::Store { rs2: rd_rs2_c }, rs1_c, uimm),
+        // C.LWSP, uimm[5|4:2|7:6], rd = 0 is reserved
+        (0b10, 0b010) if rd != 0 => (
+            Op::Load { rd, signed: true },
+            2,
+            ((insn >> 7) & 0b10_0000) | ((insn >> 2) & 0b1_1100) | ((insn << 4) & 0b1100_0000),
+        ),
+        // C.SWSP, uimm[5:2|7:6]
+        (0b10, 0b110) => (
+            Op::Store {
+                rs2: ((insn >> 2) & 0x1f) as u8,
+            },
+            2,
+            ((insn >> 7) & 0b11_1100) | ((insn >> 1) & 0b1100_0000),
+        ),
+        _ => return None,
+    };
+    Some(Access {
+        op,
+        rs1,
+        offset: offset as i32,
+        width: 4,
+        len: 2,
+    })
+}
+
+/// Sign or zero extend the low `width` bytes of `value`.
+pub fn extend(value: usize, width: usize, signed: bool) -> usize {
+    let shift = usize::BITS as usize - width * 8;
+    if signed {
+        (((value << shift) as isize) >> shift) as usize
+    } else {
+        (value << shift) >> shift
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, extend, Access, Op};
+
+    fn load(
+        rd: u8,
+        signed: bool,
+        rs1: u8,
+        offset: i32,
+        width: usize,
+        len: usize,
+    ) -> Option<Access> {
+        Some(Access {
+            op: Op::Load { rd, signed },
+            rs1,
+            offset,
+            width,
+            len,
+        })
+    }
+
+    fn store(rs2: u8, rs1: u8, offset: i32, width: usize, len: usize) -> Option<Access> {
+        Some(Access {
+            op: Op::Store { rs2 },
+            rs1,
+            offset,
+            width,
+            len,
+        })
+    }
+
+    #[test]
+    fn loads() {
+        // lb a0, -3(a1)
+        assert_eq!(decode(0xffd5_8503), load(10, true, 11, -3, 1, 4));
+        // lh a0, -2048(a1)
+        assert_eq!(decode(0x8005_9503), load(10, true, 11, -2048, 2, 4));
+        // lw t6, -4(s0)
+        assert_eq!(decode(0xffc4_2f83), load(31, true, 8, -4, 4, 4));
+        // lbu a0, -1(a1)
+        assert_eq!(decode(0xfff5_c503), load(10, false, 11, -1, 1, 4));
+        // lhu a0, -6(a1)
+        assert_eq!(decode(0xffa5_d503), load(10, false, 11, -6, 2, 4));
+    }
+
+    #[test]
+    fn stores() {
+        // sb a2, -3(a1)
+        assert_eq!(decode(0xfec5_8ea3), store(12, 11, -3, 1, 4));
+        // sh a2, -2048(a1)
+        assert_eq!(decode(0x80c5_9023), store(12, 11, -2048, 2, 4));
+        // sw t6, -4(s0)
+        assert_eq!(decode(0xfff4_2e23), store(31, 8, -4, 4, 4));
+    }
+
+    #[test]
+    fn compressed() {
+        // c.lw a0, 124(a1)
+        assert_eq!(decode(0x5de8), load(10, true, 11, 124, 4, 2));
+        // c.lw s0, 4(a5)
+        assert_eq!(decode(0x43c0), load(8, true, 15, 4, 4, 2));
+        // c.sw a2, 64(a3)
+        assert_eq!(decode(0xc2b0), store(12, 13, 64, 4, 2));
+        // c.sw a5, 120(s1)
+        assert_eq!(decode(0xdcbc), store(15, 9, 120, 4, 2));
+        // c.lwsp ra, 252(sp)
+        assert_eq!(decode(0x50fe), load(1, true, 2, 252, 4, 2));
+        // c.lwsp t6, 4(sp)
+        assert_eq!(decode(0x4f92), load(31, true, 2, 4, 4, 2));
+        // c.swsp s0, 252(sp)
+        assert_eq!(decode(0xdfa2), store(8, 2, 252, 4, 2));
+        // c.swsp t6, 4(sp)
+        assert_eq!(decode(0xc27e), store(31, 2, 4, 4, 2));
+    }
+
+    #[test]
+    fn rejects() {
+        // flw fa0, -4(a1)
+        assert_eq!(decode(0xffc5_a507), None);
+        // fsw fa0, -4(a1)
+        assert_eq!(decode(0xfea5_ae27), None);
+        // c.flw fa0, 4(a1)
+        assert_eq!(decode(0x61c8), None);
+        // c.fsw fa0, 4(a1)
+        assert_eq!(decode(0xe1c8), None);
+        // lwu a0, -4(a1), RV64
+        assert_eq!(decode(0xffc5_e503), None);
+        // ld a0, -4(a1), RV64
+        assert_eq!(decode(0xffc5_b503), None);
+        // c.lwsp with rd = 0, reserved
+        assert_eq!(decode(0x4012), None);
+    }
+
+    #[test]
+    fn extends() {
+        assert_eq!(extend(0x80, 1, true), (-0x80isize) as usize);
+        assert_eq!(extend(0x80, 1, false), 0x80);
+        assert_eq!(extend(0x1234_8000, 2, true), (-0x8000isize) as usize);
+        assert_eq!(extend(0x1234_8000, 2, false), 0x8000);
+        assert_eq!(extend(0x7fff, 2, true), 0x7fff);
+        assert_eq!(extend(0x8000_0000, 4, false), 0x8000_0000);
+        assert_eq!(extend(0x8000_0000, 4, true) as u32, 0x8000_0000);
+    }
+}
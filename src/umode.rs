@@ -0,0 +1,92 @@
+//! Running code in U-mode, isolated by the PMP.
+//!
+//! [`enter`] configures PMP entries from link.x symbols, U-mode may
+//! - read and execute `.text`
+//! - read `.rodata`
+//! - read and write the `user` sections, the statics placed with `#[place(user)]`
+//!
+//! and nothing else, accesses matching no entry fail. Entries 12 to 15 are left to the application,
+//! e.g. to give peripherals to U-mode code, see [`pmp`](crate::pmp). M-mode is not restricted.
+//!
+//! Not available with `pmp-default`: its locked `.data` and `.bss` entries apply to U-mode too.
+//!
+//! Traps from U-mode go through the usual handlers on the machine stack, [`TrapFrame::mode`] tells
+//! them apart. `ecall`s are dispatched to the `#[syscall(N)]` handlers.
+//!
+//! `#[fast]` functions and `.fast.data` are not accessible to U-mode. PLIC interrupt handlers taken
+//! in U-mode run on the machine stack too, the stub of `#[interrupt]` switches to it.
+//!
+//! ```ignore
+//! #[place(user)]
+//! static mut USER_STACK: [u8; 4096] = [0; 4096];
+//!
+//! extern "C" fn user_main() -> ! { ... }
+//!
+//! unsafe { umode::enter(user_main, addr_of_mut!(USER_STACK) as usize + 4096) }
+//! ```
+//!
+//! [`TrapFrame::mode`]: crate::trap::TrapFrame::mode
+
+use core::arch::asm;
+use core::ptr::addr_of;
+
+use crate::pmp::{self, Permission};
+
+/// Switch this hart to U-mode, running `entry` on the stack `stack_top`.
+///
+/// The calling stack becomes the machine stack, used by the trap handlers. M-mode stack overflow
+/// detection of `stack-protection` is turned off, M-mode code only runs in trap handlers from here
+/// on, which run without it.
+///
+/// # Panics
+///
+/// With the `.data` and `.bss` entries of `pmp-default` locked, they would give U-mode code access to
+/// the statics of M-mode.
+///
+/// # Safety
+///
+/// `entry` and everything it calls must be in `.text`, and `stack_top` must be the top of memory in
+/// the `user` sections, aligned to 16 bytes.
+pub unsafe fn enter(entry: extern "C" fn() -> !, stack_top: usize) -> ! {
+    extern "C" {
+        static __text_start__: u8;
+        static __text_end__: u8;
+        static __rodata_start__: u8;
+        static __rodata_end__: u8;
+        static __user_data_start__: u8;
+        static __user_end__: u8;
+    }
+
+    assert!(
+        !pmp::is_locked(8) && !pmp::is_locked(10),
+        "U-mode is not isolated from `.data` and `.bss` with `pmp-default`"
+    );
+
+    let range = |start: *const u8, end: *const u8| (start as usize, end as usize);
+    let (start, end) = range(addr_of!(__text_start__), addr_of!(__text_end__));
+    pmp::set_region(1, start, end, Permission::RX, false);
+    let (start, end) = range(addr_of!(__rodata_start__), addr_of!(__rodata_end__));
+    pmp::set_region(3, start, end, Permission::R, false);
+    let (start, end) = range(addr_of!(__user_data_start__), addr_of!(__user_end__));
+    pmp::set_region(5, start, end, Permission::RW, false);
+
+    // mhsp_ctl.OVF_EN, sp is about to leave the bounds
+    #[cfg(feature = "stack-protection")]
+    asm!("csrci 0x7c6, 1");
+
+    asm!(
+        // the machine stack for `CORE_LOCAL`
+        "csrw mscratch, sp",
+        "csrw mepc, {entry}",
+        // MPP = U, interrupts enabled after `mret`
+        "csrc mstatus, {mpp}",
+        "csrs mstatus, {mpie}",
+        "mv sp, {stack}",
+        "mret",
+        entry = in(reg) entry,
+        mpp = in(reg) 0b11 << 11,
+        mpie = in(reg) 1 << 7,
+        stack = in(reg) stack_top,
+        options(noreturn),
+    );
+}